@@ -1,4 +1,12 @@
 fn main() {
-    // No C code compilation needed anymore - everything is pure Rust!
+    // libXtst 1.2.4 gave XTestFakeRelativeMotionEvent its screen argument;
+    // the x11 crate's bindings pass it, so older releases misread the motion
+    if let Err(e) = pkg_config::Config::new()
+        .atleast_version("1.2.4")
+        .cargo_metadata(false)
+        .probe("xtst")
+    {
+        panic!("libXtst 1.2.4 or newer is required: {}", e);
+    }
     println!("cargo:rerun-if-changed=src/");
 }
@@ -14,12 +14,16 @@ BuildRequires:  wayland-protocols-devel
 BuildRequires:  glib2-devel
 BuildRequires:  libX11-devel
 BuildRequires:  libXfixes-devel
+BuildRequires:  libXrandr-devel
+BuildRequires:  libXtst-devel >= 1.2.4
 BuildRequires:  pkgconfig
 
 Requires:       libwayland-cursor
 Requires:       libwayland-egl
 Requires:       libX11
 Requires:       libXfixes
+Requires:       libXrandr
+Requires:       libXtst >= 1.2.4
 Requires:       glib2
 
 %description
@@ -0,0 +1,445 @@
+use crate::rules::{Rule, RuleFile};
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const CONFIG_FILE_NAME: &str = "config.toml";
+const SYSTEM_CONFIG_DIR: &str = "/etc/demonhide";
+
+/// Fallback screen geometry used when no monitor information is available
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScreenFallback {
+    pub width: i32,
+    pub height: i32,
+    pub scale: i32,
+}
+
+/// Tuned detection policy for Wine/Proton windows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WinePolicy {
+    pub enabled: bool,
+    /// Slack in pixels when comparing the window to a monitor; Wine often
+    /// reports sizes that are off by the decoration
+    pub fullscreen_tolerance: i32,
+    /// Only let the heuristic lock a fullscreen Wine window once its cursor
+    /// is hidden; when false it is locked with a visible cursor too
+    pub require_hidden_cursor: bool,
+}
+
+/// Effective daemon configuration after layering defaults, the system file,
+/// the user file and environment overrides.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub poll_interval: Duration,
+    pub warp_interval: Duration,
+    pub lock_delay: Duration,
+    pub unlock_delay: Duration,
+    pub screen: ScreenFallback,
+    pub wine: WinePolicy,
+    // Per-application rules, user rules before system rules
+    pub rules: Vec<Rule>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            poll_interval: Duration::from_millis(2000),
+            warp_interval: Duration::from_millis(250),
+            lock_delay: Duration::from_millis(250),
+            unlock_delay: Duration::from_millis(400),
+            screen: ScreenFallback {
+                width: 1920,
+                height: 1080,
+                scale: 1,
+            },
+            wine: WinePolicy {
+                enabled: true,
+                fullscreen_tolerance: 64,
+                require_hidden_cursor: true,
+            },
+            rules: Vec::new(),
+        }
+    }
+}
+
+// On-disk representation; every key is optional so files can be layered
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    poll_interval_ms: Option<u64>,
+    warp_interval_ms: Option<u64>,
+    lock_delay_ms: Option<u64>,
+    unlock_delay_ms: Option<u64>,
+    screen: ScreenFile,
+    wine: WineFile,
+    rules: Vec<RuleFile>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct WineFile {
+    enabled: Option<bool>,
+    fullscreen_tolerance_px: Option<i32>,
+    require_hidden_cursor: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ScreenFile {
+    width: Option<i32>,
+    height: Option<i32>,
+    scale: Option<i32>,
+}
+
+impl Config {
+    /// Load `/etc/demonhide/config.toml` and then the user config on top.
+    /// Missing files are skipped; unreadable or invalid files are returned
+    /// as an error so the caller can keep its previous configuration.
+    pub fn load() -> Result<Self, String> {
+        let mut config = Config::default();
+        for path in config_paths() {
+            match std::fs::read_to_string(&path) {
+                Ok(contents) => {
+                    let file: ConfigFile = toml::from_str(&contents)
+                        .map_err(|e| format!("{}: {}", path.display(), e))?;
+                    config
+                        .apply_file(file, &path)
+                        .map_err(|e| format!("{}: {}", path.display(), e))?;
+                    debug!("Loaded config from {}", path.display());
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(format!("{}: {}", path.display(), e)),
+            }
+        }
+        config.apply_env();
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Like `load`, but logs errors and falls back to defaults
+    pub fn load_or_default() -> Self {
+        match Config::load() {
+            Ok(config) => config,
+            Err(e) => {
+                error!("Invalid configuration, using defaults: {}", e);
+                let mut config = Config::default();
+                config.apply_env();
+                config
+            }
+        }
+    }
+
+    // `path` names the unnamed rules of `file`
+    fn apply_file(&mut self, file: ConfigFile, path: &Path) -> Result<(), String> {
+        if let Some(ms) = file.poll_interval_ms {
+            self.poll_interval = Duration::from_millis(ms);
+        }
+        if let Some(ms) = file.warp_interval_ms {
+            self.warp_interval = Duration::from_millis(ms);
+        }
+        if let Some(ms) = file.lock_delay_ms {
+            self.lock_delay = Duration::from_millis(ms);
+        }
+        if let Some(ms) = file.unlock_delay_ms {
+            self.unlock_delay = Duration::from_millis(ms);
+        }
+        if let Some(width) = file.screen.width {
+            self.screen.width = width;
+        }
+        if let Some(height) = file.screen.height {
+            self.screen.height = height;
+        }
+        if let Some(scale) = file.screen.scale {
+            self.screen.scale = scale;
+        }
+        if let Some(enabled) = file.wine.enabled {
+            self.wine.enabled = enabled;
+        }
+        if let Some(tolerance) = file.wine.fullscreen_tolerance_px {
+            self.wine.fullscreen_tolerance = tolerance;
+        }
+        if let Some(require) = file.wine.require_hidden_cursor {
+            self.wine.require_hidden_cursor = require;
+        }
+        // Later files take priority, so their rules are evaluated first
+        let mut rules = file
+            .rules
+            .into_iter()
+            .enumerate()
+            .map(|(i, rule)| Rule::compile(rule, &path.display().to_string(), i))
+            .collect::<Result<Vec<_>, _>>()?;
+        rules.append(&mut self.rules);
+        self.rules = rules;
+        Ok(())
+    }
+
+    // Environment variables keep working and take precedence over files
+    fn apply_env(&mut self) {
+        let env_i32 = |name: &str| std::env::var(name).ok().and_then(|v| v.parse::<i32>().ok());
+        let env_ms = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .map(Duration::from_millis)
+        };
+        if let Some(width) = env_i32("WAYLAND_SCREEN_WIDTH") {
+            self.screen.width = width;
+        }
+        if let Some(height) = env_i32("WAYLAND_SCREEN_HEIGHT") {
+            self.screen.height = height;
+        }
+        if let Some(scale) = env_i32("WAYLAND_SCREEN_SCALE") {
+            self.screen.scale = scale;
+        }
+        if let Some(delay) = env_ms("DEMONHIDE_LOCK_DELAY_MS") {
+            self.lock_delay = delay;
+        }
+        if let Some(delay) = env_ms("DEMONHIDE_UNLOCK_DELAY_MS") {
+            self.unlock_delay = delay;
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.poll_interval.is_zero() {
+            return Err("poll_interval_ms must be greater than 0".into());
+        }
+        if self.warp_interval.is_zero() {
+            return Err("warp_interval_ms must be greater than 0".into());
+        }
+        if self.screen.width <= 0 || self.screen.height <= 0 || self.screen.scale <= 0 {
+            return Err("screen width, height and scale must be positive".into());
+        }
+        if self.wine.fullscreen_tolerance < 0 {
+            return Err("wine.fullscreen_tolerance_px must not be negative".into());
+        }
+        Ok(())
+    }
+}
+
+// User config directory: $XDG_CONFIG_HOME/demonhide or ~/.config/demonhide
+fn user_config_dir() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("demonhide"))
+}
+
+// Config files in increasing priority order
+fn config_paths() -> Vec<PathBuf> {
+    let mut paths = vec![Path::new(SYSTEM_CONFIG_DIR).join(CONFIG_FILE_NAME)];
+    if let Some(dir) = user_config_dir() {
+        paths.push(dir.join(CONFIG_FILE_NAME));
+    }
+    paths
+}
+
+/// inotify watch on the config directories. The fd is meant to be watched
+/// by the glib main loop; call `has_changes` whenever it becomes readable.
+pub struct ConfigWatcher {
+    inotify: Inotify,
+    // Directories currently watched, replaced whenever they are re-resolved
+    watches: Vec<WatchDescriptor>,
+}
+
+impl ConfigWatcher {
+    pub fn new() -> Option<Self> {
+        let inotify = match Inotify::init() {
+            Ok(inotify) => inotify,
+            Err(e) => {
+                warn!(
+                    "Could not initialise inotify, config live reload disabled: {}",
+                    e
+                );
+                return None;
+            }
+        };
+        let mut watcher = ConfigWatcher {
+            inotify,
+            watches: Vec::new(),
+        };
+        if watcher.add_watches() == 0 {
+            return None;
+        }
+        info!("Watching configuration files for changes");
+        Some(watcher)
+    }
+
+    // (Re)register watches on every config directory, dropping watches on
+    // ancestors that are no longer the nearest existing one; returns how
+    // many succeeded
+    fn add_watches(&mut self) -> usize {
+        let mask = WatchMask::CLOSE_WRITE
+            | WatchMask::MOVED_TO
+            | WatchMask::MOVED_FROM
+            | WatchMask::CREATE
+            | WatchMask::DELETE;
+        let mut watches = Vec::new();
+        for path in config_paths() {
+            let Some(dir) = path.parent() else {
+                continue;
+            };
+            // Watch the nearest existing ancestor so a config directory
+            // created later is noticed too
+            let Some(target) = dir.ancestors().find(|d| d.is_dir()) else {
+                continue;
+            };
+            match self.inotify.watches().add(target, mask) {
+                Ok(wd) => {
+                    debug!("Watching {} for config changes", target.display());
+                    watches.push(wd);
+                }
+                Err(e) => warn!("Could not watch {}: {}", target.display(), e),
+            }
+        }
+        for wd in self.watches.drain(..) {
+            if !watches.contains(&wd) {
+                // Fails harmlessly if the directory was deleted meanwhile
+                let _ = self.inotify.watches().remove(wd);
+            }
+        }
+        let watched = watches.len();
+        self.watches = watches;
+        watched
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.inotify.as_raw_fd()
+    }
+
+    /// Drain pending inotify events. Returns true when a config file (or a
+    /// directory that may contain one) changed and the config should be
+    /// reloaded.
+    pub fn has_changes(&mut self) -> bool {
+        let mut buffer = [0u8; 4096];
+        let mut changed = false;
+        let mut dir_changed = false;
+        loop {
+            match self.inotify.read_events(&mut buffer) {
+                Ok(events) => {
+                    let mut any = false;
+                    for event in events {
+                        any = true;
+                        // Any directory appearing or leaving under a watched
+                        // ancestor (`.config` below `~` too) may move the
+                        // nearest existing config directory
+                        if event.mask.contains(EventMask::ISDIR) {
+                            dir_changed = true;
+                        }
+                        if event.name.is_some_and(|name| name == CONFIG_FILE_NAME) {
+                            changed = true;
+                        }
+                    }
+                    if !any {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!("Error reading config change events: {}", e);
+                    break;
+                }
+            }
+        }
+        // Re-resolve the watched directories; the config file may already
+        // exist in a directory created before its watch was added
+        if dir_changed {
+            self.add_watches();
+            changed = true;
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(contents: &str) -> ConfigFile {
+        toml::from_str(contents).expect("valid config")
+    }
+
+    #[test]
+    fn later_files_override_earlier_ones() {
+        let mut config = Config::default();
+        config
+            .apply_file(
+                parse("poll_interval_ms = 1000\nlock_delay_ms = 100\n[wine]\nenabled = false\n"),
+                Path::new("config.toml"),
+            )
+            .unwrap();
+        config
+            .apply_file(
+                parse("lock_delay_ms = 50\n[screen]\nwidth = 2560\n"),
+                Path::new("config.toml"),
+            )
+            .unwrap();
+        assert_eq!(config.poll_interval, Duration::from_millis(1000));
+        assert_eq!(config.lock_delay, Duration::from_millis(50));
+        assert_eq!(config.unlock_delay, Config::default().unlock_delay);
+        assert_eq!(config.screen.width, 2560);
+        assert_eq!(config.screen.height, 1080);
+        assert!(!config.wine.enabled);
+        assert!(config.wine.require_hidden_cursor);
+    }
+
+    #[test]
+    fn later_files_rules_come_first() {
+        let mut config = Config::default();
+        config
+            .apply_file(
+                parse("[[rules]]\nname = \"system\"\nclass = \"game\"\naction = \"never\"\n"),
+                Path::new("system.toml"),
+            )
+            .unwrap();
+        config
+            .apply_file(
+                parse(
+                    "[[rules]]\nname = \"user\"\nclass = \"game\"\naction = \"always\"\n\
+                 [[rules]]\nclass = \"other\"\naction = \"never\"\n",
+                ),
+                Path::new("user.toml"),
+            )
+            .unwrap();
+        let names: Vec<&str> = config.rules.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["user", "user.toml rule #2", "system"]);
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(toml::from_str::<ConfigFile>("poll_interval = 500\n").is_err());
+        assert!(toml::from_str::<ConfigFile>("[wine]\nenable = true\n").is_err());
+        assert!(toml::from_str::<ConfigFile>("[screen]\ndepth = 24\n").is_err());
+        assert!(toml::from_str::<ConfigFile>(
+            "[[rules]]\nclass = \"game\"\naction = \"never\"\npriority = 1\n"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn invalid_rules_and_values_are_errors() {
+        let mut config = Config::default();
+        assert!(config
+            .apply_file(
+                parse("[[rules]]\naction = \"always\"\n"),
+                Path::new("config.toml")
+            )
+            .is_err());
+        assert!(config
+            .apply_file(
+                parse("[[rules]]\ntitle = \"(unclosed\"\naction = \"always\"\n"),
+                Path::new("config.toml")
+            )
+            .is_err());
+
+        let mut config = Config::default();
+        config
+            .apply_file(parse("warp_interval_ms = 0\n"), Path::new("config.toml"))
+            .unwrap();
+        assert!(config.validate().is_err());
+        assert!(Config::default().validate().is_ok());
+    }
+}
@@ -0,0 +1,505 @@
+use crate::x11_watcher::CursorImage;
+use crate::AppData;
+use log::{debug, warn};
+use std::io::Write;
+use std::os::raw::c_ulong;
+use std::os::unix::io::{AsFd, FromRawFd, OwnedFd};
+use wayland_client::protocol::{
+    wl_buffer, wl_compositor, wl_output, wl_pointer, wl_shm, wl_shm_pool, wl_surface,
+};
+use wayland_client::{Connection, Dispatch, QueueHandle};
+use wayland_protocols::wp::viewporter::client::{wp_viewport, wp_viewporter};
+use wayland_protocols::xdg::shell::client::{xdg_surface, xdg_toplevel, xdg_wm_base};
+use wayland_protocols_wlr::layer_shell::v1::client::{zwlr_layer_shell_v1, zwlr_layer_surface_v1};
+
+// Layer-shell namespace and xdg app id, lets compositors and users match our surface
+const NAMESPACE: &str = "demonhide";
+
+/// Surface the pointer is locked to. Compositors only activate a lock once
+/// the surface has pointer focus, which a role-less surface never gets, so
+/// it is mapped covering the game's output: as a layer-shell overlay where
+/// available, otherwise as a fullscreen xdg_toplevel (e.g. on Mutter).
+pub enum LockSurface {
+    /// Transparent, input-capable layer-shell overlay
+    Layer {
+        surface: wl_surface::WlSurface,
+        layer_surface: zwlr_layer_surface_v1::ZwlrLayerSurfaceV1,
+        // Stretches the 1x1 buffer over the whole output
+        viewport: Option<wp_viewport::WpViewport>,
+    },
+    /// Transparent, borderless fullscreen toplevel
+    Xdg {
+        surface: wl_surface::WlSurface,
+        xdg_surface: xdg_surface::XdgSurface,
+        toplevel: xdg_toplevel::XdgToplevel,
+        viewport: Option<wp_viewport::WpViewport>,
+        // Size from the last xdg_toplevel configure, applied on xdg_surface configure
+        pending_size: (i32, i32),
+        // Whether the toplevel has keyboard focus; None until it first gets it
+        keyboard_focus: Option<bool>,
+    },
+    /// Plain surface without a role, for compositors lacking both shells
+    Bare { surface: wl_surface::WlSurface },
+}
+
+impl LockSurface {
+    /// Create the lock target on `output` (the compositor picks one when
+    /// None). Layer and xdg surfaces are mapped when their first configure
+    /// is dispatched; a constraint requested before that is activated by
+    /// the compositor once the surface is mapped and has pointer focus.
+    pub fn create(
+        app_data: &AppData,
+        output: Option<&wl_output::WlOutput>,
+        qh: &QueueHandle<AppData>,
+    ) -> Option<Self> {
+        let compositor = app_data.compositor.as_ref()?;
+        let surface = compositor.create_surface(qh, ());
+        let lock_surface = match (&app_data.layer_shell, &app_data.wm_base) {
+            (Some(layer_shell), _) => {
+                let viewport = viewport(app_data, &surface, qh);
+                Self::create_layer(layer_shell, surface, viewport, output, qh)
+            }
+            (None, Some(wm_base)) => {
+                let viewport = viewport(app_data, &surface, qh);
+                Self::create_xdg(wm_base, surface, viewport, output, qh)
+            }
+            (None, None) => {
+                debug!("Created bare surface for pointer locking");
+                LockSurface::Bare { surface }
+            }
+        };
+        // Initial commit without a buffer asks the compositor for a configure
+        lock_surface.surface().commit();
+        Some(lock_surface)
+    }
+
+    fn create_layer(
+        layer_shell: &zwlr_layer_shell_v1::ZwlrLayerShellV1,
+        surface: wl_surface::WlSurface,
+        viewport: Option<wp_viewport::WpViewport>,
+        output: Option<&wl_output::WlOutput>,
+        qh: &QueueHandle<AppData>,
+    ) -> Self {
+        let layer_surface = layer_shell.get_layer_surface(
+            &surface,
+            output,
+            zwlr_layer_shell_v1::Layer::Overlay,
+            NAMESPACE.to_string(),
+            qh,
+            (),
+        );
+        if viewport.is_some() {
+            // Size 0x0 with every edge anchored: the compositor sends the output size
+            layer_surface.set_anchor(
+                zwlr_layer_surface_v1::Anchor::Top
+                    | zwlr_layer_surface_v1::Anchor::Bottom
+                    | zwlr_layer_surface_v1::Anchor::Left
+                    | zwlr_layer_surface_v1::Anchor::Right,
+            );
+            layer_surface.set_size(0, 0);
+        } else {
+            // Without wp_viewporter the surface is just the 1x1 buffer,
+            // centred on the output where the cursor is warped to
+            layer_surface.set_size(1, 1);
+        }
+        layer_surface.set_exclusive_zone(-1);
+        layer_surface
+            .set_keyboard_interactivity(zwlr_layer_surface_v1::KeyboardInteractivity::None);
+        debug!("Created layer-shell overlay surface for pointer locking");
+        LockSurface::Layer {
+            surface,
+            layer_surface,
+            viewport,
+        }
+    }
+
+    // Fullscreen toplevel fallback for compositors without layer-shell
+    fn create_xdg(
+        wm_base: &xdg_wm_base::XdgWmBase,
+        surface: wl_surface::WlSurface,
+        viewport: Option<wp_viewport::WpViewport>,
+        output: Option<&wl_output::WlOutput>,
+        qh: &QueueHandle<AppData>,
+    ) -> Self {
+        let xdg_surface = wm_base.get_xdg_surface(&surface, qh, ());
+        let toplevel = xdg_surface.get_toplevel(qh, ());
+        toplevel.set_title(NAMESPACE.to_string());
+        toplevel.set_app_id(NAMESPACE.to_string());
+        toplevel.set_fullscreen(output);
+        debug!("Created fullscreen xdg_toplevel for pointer locking");
+        LockSurface::Xdg {
+            surface,
+            xdg_surface,
+            toplevel,
+            viewport,
+            pending_size: (0, 0),
+            keyboard_focus: None,
+        }
+    }
+
+    pub fn surface(&self) -> &wl_surface::WlSurface {
+        match self {
+            LockSurface::Layer { surface, .. }
+            | LockSurface::Xdg { surface, .. }
+            | LockSurface::Bare { surface } => surface,
+        }
+    }
+
+    /// Apply a constraint's region or cursor position hint, which are
+    /// double-buffered state taking effect on the surface's next commit
+    pub fn apply_constraint_state(&self) {
+        self.surface().commit();
+    }
+
+    /// Unmap and destroy the surface and its role
+    pub fn destroy(self) {
+        match self {
+            LockSurface::Layer {
+                surface,
+                layer_surface,
+                viewport,
+            } => {
+                if let Some(viewport) = viewport {
+                    viewport.destroy();
+                }
+                layer_surface.destroy();
+                surface.destroy();
+            }
+            LockSurface::Xdg {
+                surface,
+                xdg_surface,
+                toplevel,
+                viewport,
+                ..
+            } => {
+                if let Some(viewport) = viewport {
+                    viewport.destroy();
+                }
+                toplevel.destroy();
+                xdg_surface.destroy();
+                surface.destroy();
+            }
+            LockSurface::Bare { surface } => surface.destroy(),
+        }
+        debug!("Destroyed pointer lock surface");
+    }
+}
+
+// Viewport stretching the lock surface's 1x1 buffer over the output
+fn viewport(
+    app_data: &AppData,
+    surface: &wl_surface::WlSurface,
+    qh: &QueueHandle<AppData>,
+) -> Option<wp_viewport::WpViewport> {
+    let Some(viewporter) = &app_data.viewporter else {
+        warn!("wp_viewporter not available, lock surface covers a single pixel");
+        return None;
+    };
+    Some(viewporter.get_viewport(surface, qh, ()))
+}
+
+// The compositor closed the lock surface. The constraint is useless without
+// its surface; dropping it lets the daemon lock again with a new one.
+fn lock_surface_closed(state: &mut AppData) {
+    debug!("Compositor closed the lock surface");
+    state.drop_constraints();
+    state.destroy_lock_surface();
+}
+
+// Attach the transparent buffer, stretched to `width`x`height` when a
+// viewport is available. Attaching a buffer maps the surface.
+fn attach_buffer(
+    surface: &wl_surface::WlSurface,
+    viewport: Option<&wp_viewport::WpViewport>,
+    buffer: Option<&wl_buffer::WlBuffer>,
+    width: i32,
+    height: i32,
+) {
+    if let Some(viewport) = viewport {
+        if width > 0 && height > 0 {
+            viewport.set_destination(width, height);
+        }
+    }
+    surface.attach(buffer, 0, 0);
+    surface.commit();
+}
+
+/// A 1x1 fully transparent ARGB8888 buffer backed by an anonymous memfd
+pub fn transparent_buffer(
+    shm: &wl_shm::WlShm,
+    qh: &QueueHandle<AppData>,
+) -> Option<wl_buffer::WlBuffer> {
+    // A zero-filled pixel is fully transparent
+    shm_buffer(shm, qh, 1, 1, &[0])
+}
+
+// A `width`x`height` ARGB8888 buffer holding `pixels`, backed by an
+// anonymous memfd
+fn shm_buffer(
+    shm: &wl_shm::WlShm,
+    qh: &QueueHandle<AppData>,
+    width: i32,
+    height: i32,
+    pixels: &[u32],
+) -> Option<wl_buffer::WlBuffer> {
+    let fd = unsafe { libc::memfd_create(c"demonhide-shm".as_ptr(), libc::MFD_CLOEXEC) };
+    if fd < 0 {
+        warn!(
+            "Could not create shm memfd: {}",
+            std::io::Error::last_os_error()
+        );
+        return None;
+    }
+    let mut file = std::fs::File::from(unsafe { OwnedFd::from_raw_fd(fd) });
+    // wl_shm formats are little-endian
+    let bytes: Vec<u8> = pixels.iter().flat_map(|pixel| pixel.to_le_bytes()).collect();
+    file.write_all(&bytes)
+        .map_err(|e| warn!("Could not fill shm file: {}", e))
+        .ok()?;
+    // The fd is duplicated when the request is sent, so the file can be dropped
+    let pool = shm.create_pool(file.as_fd(), bytes.len() as i32, qh, ());
+    let buffer = pool.create_buffer(0, width, height, width * 4, wl_shm::Format::Argb8888, qh, ());
+    pool.destroy();
+    Some(buffer)
+}
+
+/// Cursor surface showing the game's X cursor while the pointer is over
+/// the lock surface. The lock surface holds pointer focus instead of the
+/// game's window, so the compositor would otherwise show its own cursor or
+/// none, hiding e.g. a confined strategy game's cursor.
+pub struct GameCursor {
+    surface: wl_surface::WlSurface,
+    buffer: Option<wl_buffer::WlBuffer>,
+    hotspot: (i32, i32),
+    // XFixes cursor_serial of the image shown
+    serial: c_ulong,
+}
+
+impl GameCursor {
+    pub fn new(compositor: &wl_compositor::WlCompositor, qh: &QueueHandle<AppData>) -> Self {
+        GameCursor {
+            surface: compositor.create_surface(qh, ()),
+            buffer: None,
+            hotspot: (0, 0),
+            serial: 0,
+        }
+    }
+
+    pub fn serial(&self) -> c_ulong {
+        self.serial
+    }
+
+    /// Show `image` from now on; an empty image shows no cursor
+    pub fn set_image(&mut self, shm: &wl_shm::WlShm, image: &CursorImage, qh: &QueueHandle<AppData>) {
+        let complete = image.pixels.len() == (image.width * image.height) as usize;
+        let buffer = if image.width > 0 && image.height > 0 && complete {
+            shm_buffer(shm, qh, image.width, image.height, &image.pixels)
+        } else {
+            None
+        };
+        self.surface.attach(buffer.as_ref(), 0, 0);
+        self.surface.damage_buffer(0, 0, image.width, image.height);
+        self.surface.commit();
+        // The old buffer's memory is never written again, so it may go
+        // before the compositor releases it
+        if let Some(old) = std::mem::replace(&mut self.buffer, buffer) {
+            old.destroy();
+        }
+        self.hotspot = image.hotspot;
+        self.serial = image.serial;
+    }
+
+    /// Use as the cursor while the pointer stays on the surface it entered
+    /// with `enter_serial`
+    pub fn apply(&self, pointer: &wl_pointer::WlPointer, enter_serial: u32) {
+        pointer.set_cursor(enter_serial, Some(&self.surface), self.hotspot.0, self.hotspot.1);
+    }
+
+    pub fn destroy(self) {
+        if let Some(buffer) = self.buffer {
+            buffer.destroy();
+        }
+        self.surface.destroy();
+    }
+}
+
+impl Dispatch<zwlr_layer_surface_v1::ZwlrLayerSurfaceV1, ()> for AppData {
+    fn event(
+        state: &mut Self,
+        layer_surface: &zwlr_layer_surface_v1::ZwlrLayerSurfaceV1,
+        event: zwlr_layer_surface_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<AppData>,
+    ) {
+        match event {
+            zwlr_layer_surface_v1::Event::Configure {
+                serial,
+                width,
+                height,
+            } => {
+                debug!("Layer surface configure: {}x{}", width, height);
+                layer_surface.ack_configure(serial);
+                if let Some(LockSurface::Layer {
+                    surface, viewport, ..
+                }) = &state.lock_surface
+                {
+                    attach_buffer(
+                        surface,
+                        viewport.as_ref(),
+                        state.lock_buffer.as_ref(),
+                        width as i32,
+                        height as i32,
+                    );
+                }
+            }
+            zwlr_layer_surface_v1::Event::Closed => {
+                lock_surface_closed(state);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<xdg_wm_base::XdgWmBase, ()> for AppData {
+    fn event(
+        _: &mut Self,
+        wm_base: &xdg_wm_base::XdgWmBase,
+        event: xdg_wm_base::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<AppData>,
+    ) {
+        // The compositor may consider us unresponsive without a pong
+        if let xdg_wm_base::Event::Ping { serial } = event {
+            wm_base.pong(serial);
+        }
+    }
+}
+
+impl Dispatch<xdg_surface::XdgSurface, ()> for AppData {
+    fn event(
+        state: &mut Self,
+        xdg_surface: &xdg_surface::XdgSurface,
+        event: xdg_surface::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<AppData>,
+    ) {
+        if let xdg_surface::Event::Configure { serial } = event {
+            // Ends a configure sequence: ack it, then commit the state it asked for
+            xdg_surface.ack_configure(serial);
+            if let Some(LockSurface::Xdg {
+                surface,
+                viewport,
+                pending_size: (width, height),
+                ..
+            }) = &state.lock_surface
+            {
+                debug!("xdg_surface configure: {}x{}", width, height);
+                attach_buffer(
+                    surface,
+                    viewport.as_ref(),
+                    state.lock_buffer.as_ref(),
+                    *width,
+                    *height,
+                );
+            }
+        }
+    }
+}
+
+impl Dispatch<xdg_toplevel::XdgToplevel, ()> for AppData {
+    fn event(
+        state: &mut Self,
+        _: &xdg_toplevel::XdgToplevel,
+        event: xdg_toplevel::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<AppData>,
+    ) {
+        match event {
+            xdg_toplevel::Event::Configure { width, height, .. } => {
+                if let Some(LockSurface::Xdg { pending_size, .. }) = &mut state.lock_surface {
+                    *pending_size = (width, height);
+                }
+            }
+            xdg_toplevel::Event::Close => {
+                lock_surface_closed(state);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<zwlr_layer_shell_v1::ZwlrLayerShellV1, ()> for AppData {
+    fn event(
+        _: &mut Self,
+        _: &zwlr_layer_shell_v1::ZwlrLayerShellV1,
+        _: zwlr_layer_shell_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<AppData>,
+    ) {
+    }
+}
+
+impl Dispatch<wp_viewporter::WpViewporter, ()> for AppData {
+    fn event(
+        _: &mut Self,
+        _: &wp_viewporter::WpViewporter,
+        _: wp_viewporter::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<AppData>,
+    ) {
+    }
+}
+
+impl Dispatch<wp_viewport::WpViewport, ()> for AppData {
+    fn event(
+        _: &mut Self,
+        _: &wp_viewport::WpViewport,
+        _: wp_viewport::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<AppData>,
+    ) {
+    }
+}
+
+impl Dispatch<wl_shm::WlShm, ()> for AppData {
+    fn event(
+        _: &mut Self,
+        _: &wl_shm::WlShm,
+        _: wl_shm::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<AppData>,
+    ) {
+    }
+}
+
+impl Dispatch<wl_shm_pool::WlShmPool, ()> for AppData {
+    fn event(
+        _: &mut Self,
+        _: &wl_shm_pool::WlShmPool,
+        _: wl_shm_pool::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<AppData>,
+    ) {
+    }
+}
+
+impl Dispatch<wl_buffer::WlBuffer, ()> for AppData {
+    fn event(
+        _: &mut Self,
+        _: &wl_buffer::WlBuffer,
+        _: wl_buffer::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<AppData>,
+    ) {
+    }
+}
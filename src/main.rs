@@ -1,240 +1,770 @@
+mod config;
+mod lock_surface;
+mod relative_motion;
+mod rules;
+mod steam;
+mod window_info;
+mod wine;
+mod x11_watcher;
+mod xwayland;
+
 use glib::MainLoop;
 use log::{debug, info, warn, error};
-use std::ptr;
 use wayland_client::protocol::{
-    wl_compositor, wl_output, wl_pointer, wl_registry, wl_seat, wl_shell, wl_shell_surface,
-    wl_surface,
+    wl_buffer, wl_callback, wl_compositor, wl_keyboard, wl_output, wl_pointer, wl_region,
+    wl_registry, wl_seat, wl_shm, wl_surface,
 };
-use wayland_client::{Connection, Dispatch, QueueHandle};
+use wayland_client::backend::WaylandError;
+use wayland_client::{Connection, Dispatch, Proxy, QueueHandle};
 use wayland_protocols::wp::pointer_constraints::zv1::client::{
-    zwp_locked_pointer_v1, zwp_pointer_constraints_v1,
+    zwp_confined_pointer_v1, zwp_locked_pointer_v1, zwp_pointer_constraints_v1,
+};
+use wayland_protocols::wp::pointer_warp::v1::client::wp_pointer_warp_v1;
+use wayland_protocols::wp::relative_pointer::zv1::client::{
+    zwp_relative_pointer_manager_v1, zwp_relative_pointer_v1,
 };
+use wayland_protocols::wp::viewporter::client::wp_viewporter;
+use wayland_protocols::xdg::shell::client::xdg_wm_base;
+use wayland_protocols::xdg::xdg_output::zv1::client::{zxdg_output_manager_v1, zxdg_output_v1};
+use wayland_protocols_wlr::layer_shell::v1::client::zwlr_layer_shell_v1;
+use wayland_protocols_wlr::virtual_pointer::v1::client::{
+    zwlr_virtual_pointer_manager_v1, zwlr_virtual_pointer_v1,
+};
+use config::{Config, ConfigWatcher};
+use lock_surface::{GameCursor, LockSurface};
+use relative_motion::MotionForwarder;
+use rules::ConstraintMode;
+use window_info::{WindowInfo, WindowInfoCache};
+use x11_watcher::X11Watcher;
+use xwayland::XWaylandLocator;
+
+// How often to try reconnecting to the X server while disconnected
+const X11_RECONNECT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+// Poll interval cap while focus can only be read with XGetInputFocus, which
+// sends no events
+const FOCUS_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+// Backoff bounds for reconnecting to the compositor
+const WAYLAND_RECONNECT_MIN: std::time::Duration = std::time::Duration::from_secs(1);
+const WAYLAND_RECONNECT_MAX: std::time::Duration = std::time::Duration::from_secs(30);
+// How long to wait for the compositor to answer while connecting
+const WAYLAND_CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+// Result of one detection pass over the focused XWayland window
+#[derive(Debug, Clone, Default)]
+struct Detection {
+    should_lock: bool,
+    // Client toplevel the decision applies to (None when nothing is focused)
+    window: Option<x11::xlib::Window>,
+    // Name of the per-application rule that decided, if any
+    rule: Option<String>,
+    // Lock the pointer in place or confine it to the window
+    mode: ConstraintMode,
+    // Client window rectangle in root coordinates (x, y, width, height)
+    rect: Option<(i32, i32, i32, i32)>,
+}
 
-fn should_lock_pointer() -> bool {
+// `watcher` is the daemon's X11 connection with event-tracked cursor and
+// focus state; `config.rules` are evaluated before the fullscreen/cursor
+// heuristic; `cache` keeps window identities across passes
+fn should_lock_pointer(watcher: Option<&X11Watcher>, config: &Config, cache: &mut WindowInfoCache) -> Detection {
     // Check if we're in a Wayland session
     if std::env::var("WAYLAND_DISPLAY").is_err() {
-        return false; // Not in Wayland
+        return Detection::default(); // Not in Wayland
     }
 
     // Check if there's an X11 display (XWayland)
-    if std::env::var("DISPLAY").is_err() {
-        return false; // No XWayland
+    let Some(watcher) = watcher else {
+        return Detection::default(); // No XWayland
+    };
+    if watcher.is_connection_lost() {
+        return Detection::default();
     }
 
     // Check XWayland for fullscreen applications with hidden cursor
-    check_xwayland_fullscreen_with_hidden_cursor()
+    x11_watcher::clear_error(watcher.display());
+    let detection = check_xwayland_fullscreen_with_hidden_cursor(watcher, config, cache);
+    // A protocol error means the window changed under us (typically
+    // BadWindow after it was destroyed); treat it as not fullscreen
+    if let Some(code) = x11_watcher::take_error() {
+        debug!("X error {} during detection, not locking", code);
+        return Detection { should_lock: false, rule: None, ..detection };
+    }
+    detection
 }
 
-fn check_xwayland_fullscreen_with_hidden_cursor() -> bool {
+fn check_xwayland_fullscreen_with_hidden_cursor(watcher: &X11Watcher, config: &Config, cache: &mut WindowInfoCache) -> Detection {
     unsafe {
-    use std::os::raw::{c_int, c_uchar, c_ulong};
-
-        // Helper: check whether the cursor is hidden via XFixes
-        let is_cursor_hidden = |display: *mut x11::xlib::Display| -> bool {
-            let mut event_base: c_int = 0;
-            let mut error_base: c_int = 0;
-            if x11::xfixes::XFixesQueryExtension(display, &mut event_base, &mut error_base) == 0 {
-                return false; // cannot query cursor
-            }
-            let cursor_image = x11::xfixes::XFixesGetCursorImage(display);
-            if cursor_image.is_null() {
-                return true; // assume hidden if we can't get image
-            }
-            let cursor = &*cursor_image;
-            let hidden = cursor.width <= 1 && cursor.height <= 1;
-            x11::xlib::XFree(cursor_image as *mut _);
-            hidden
+        let display = watcher.display();
+        let atoms = watcher.atoms();
+        let root = watcher.root();
+        let screen = x11::xlib::XDefaultScreen(display);
+        let screen_width = x11::xlib::XDisplayWidth(display, screen);
+        let screen_height = x11::xlib::XDisplayHeight(display, screen);
+
+        let Some(focus_window) = watcher.focused_window() else {
+            return Detection::default();
         };
 
-        // Helper: check STEAM_GAME atom presence on a window (treat presence as Steam game)
-        let is_steam_game_window = |display: *mut x11::xlib::Display, w: x11::xlib::Window| -> bool {
-            let atom_name = std::ffi::CString::new("STEAM_GAME").unwrap();
-            let atom = x11::xlib::XInternAtom(display, atom_name.as_ptr(), 0);
-            if atom == 0 {
-                return false;
-            }
-            // Query the property but treat any non-empty result as presence
-            let mut actual_type: x11::xlib::Atom = 0;
-            let mut actual_format: c_int = 0;
-            let mut nitems: c_ulong = 0;
-            let mut bytes_after: c_ulong = 0;
-            let mut prop: *mut c_uchar = std::ptr::null_mut();
-            let res = x11::xlib::XGetWindowProperty(
-                display,
-                w,
-                atom,
-                0,
-                1,
-                0,
-                x11::xlib::AnyPropertyType as x11::xlib::Atom,
-                &mut actual_type,
-                &mut actual_format,
-                &mut nitems,
-                &mut bytes_after,
-                &mut prop,
-            );
-            if res != 0 || prop.is_null() || nitems == 0 {
-                if !prop.is_null() {
-                    x11::xlib::XFree(prop as *mut _);
+        // Classify the client toplevel rather than a focused child or WM frame
+        let focused = focus_window;
+        let focus_window = x11_watcher::resolve_client_window(display, atoms, focus_window);
+        let rect = window_rect(display, root, focus_window);
+        let base = Detection { window: Some(focus_window), rect, ..Detection::default() };
+
+        // Without rules or a Wine policy accepting visible cursors, a visible
+        // cursor never locks: skip identifying the window
+        let cursor_hidden = watcher.cursor_hidden();
+        let wine_cursor_optional = config.wine.enabled && !config.wine.require_hidden_cursor;
+        if config.rules.is_empty() && !cursor_hidden && !wine_cursor_optional {
+            return base;
+        }
+        let info = WindowInfo::query(display, atoms, focus_window, focused, cache);
+        let wine = config.wine.enabled && info.is_wine;
+
+        // Per-application rules win over the heuristic and the Wine policy;
+        // first match decides
+        let mut matched_rule = None;
+        let mut mode = ConstraintMode::default();
+        if !config.rules.is_empty() {
+            if let Some(rule) = rules::first_match(&config.rules, &info) {
+                debug!("Rule '{}' matched window {} (action {:?}, mode {:?})", rule.name, focus_window, rule.action, rule.mode);
+                let should_lock = match rule.action {
+                    rules::RuleAction::Always => Some(true),
+                    rules::RuleAction::Never => Some(false),
+                    rules::RuleAction::Heuristic => None,
+                };
+                if let Some(should_lock) = should_lock {
+                    return Detection { should_lock, rule: Some(rule.name.clone()), mode: rule.mode, ..base };
                 }
-                return false;
+                matched_rule = Some(rule.name.clone());
+                mode = rule.mode;
             }
-            x11::xlib::XFree(prop as *mut _);
-            true
-        };
+        }
 
-        let display = x11::xlib::XOpenDisplay(ptr::null());
-        if display.is_null() {
-            return false;
+        // Wine/Proton maps SetCursor(NULL) onto a transparent cursor, so a
+        // hidden cursor is normally required; the Wine policy can waive it
+        // for fullscreen Wine windows
+        let cursor_accepted = cursor_hidden || (wine && !config.wine.require_hidden_cursor);
+
+        // Check if steam game first (no fullscreen requirement)
+        // STEAM_GAME on the client or focused window, SteamAppId/SteamGameId in
+        // the process environment, or a process running under Steam's reaper
+        let steam_present = info.steam.is_some();
+        debug!("X11 focused={} steam={:?} cursor_hidden={}", focus_window, info.steam, cursor_hidden);
+        if steam_present && cursor_hidden {
+            return Detection { should_lock: true, rule: matched_rule, mode, ..base };
         }
 
-        let root = x11::xlib::XDefaultRootWindow(display);
-        let screen = x11::xlib::XDefaultScreen(display);
-        let screen_width = x11::xlib::XDisplayWidth(display, screen);
-        let screen_height = x11::xlib::XDisplayHeight(display, screen);
+        let Some(window_rect) = rect else {
+            return Detection { rule: matched_rule, ..base };
+        };
 
-        // Get the currently focused window
-        let mut focus_window: x11::xlib::Window = 0;
-        let mut revert_to: c_int = 0;
-        x11::xlib::XGetInputFocus(display, &mut focus_window, &mut revert_to);
+        // Primary signal: the WM marks the window with _NET_WM_STATE_FULLSCREEN
+        let ewmh_fullscreen = is_ewmh_fullscreen(display, atoms, focus_window);
 
-        if focus_window == 0 || focus_window == root {
-            x11::xlib::XCloseDisplay(display);
-            return false;
-        }
+        // Games faking fullscreen by resizing to exactly one monitor
+        let monitors = watcher.monitors();
+        // Wine windows get slack for sizes that are off by the decoration
+        let tolerance = if wine { config.wine.fullscreen_tolerance } else { 0 };
+        let covered_monitor = monitors.iter().find(|m| rect_covers(window_rect, **m, tolerance));
 
-        // Check if steam game first (no fullscreen requirement)
-        let cursor_hidden = is_cursor_hidden(display);
-        let steam_present = is_steam_game_window(display, focus_window);
-        debug!("X11 focused={} steam_present={} cursor_hidden={}", focus_window, steam_present, cursor_hidden);
-        if steam_present && cursor_hidden {
-            x11::xlib::XCloseDisplay(display);
-            return true;
+        // Fallback: window covers the entire root (breaks on multi-monitor XWayland,
+        // where the root spans every output)
+        let covers_screen = window_rect.2 + tolerance >= screen_width
+            && window_rect.3 + tolerance >= screen_height;
+
+        let is_fullscreen = ewmh_fullscreen || covered_monitor.is_some() || covers_screen;
+
+        debug!("Focused={} window_rect={:?} screen_size={}x{} monitors={:?} ewmh_fullscreen={} covered_monitor={:?} covers_screen={} is_fullscreen={} cursor_hidden={} steam_present={} wine={}",
+            focus_window, window_rect, screen_width, screen_height, monitors, ewmh_fullscreen, covered_monitor, covers_screen, is_fullscreen, cursor_hidden, steam_present, wine);
+
+        // If fullscreen and cursor hidden (or waived for Wine), return true
+        if is_fullscreen && cursor_accepted {
+            return Detection { should_lock: true, rule: matched_rule, mode, ..base };
         }
 
-        // Get window attributes
-        let mut window_attrs: x11::xlib::XWindowAttributes = std::mem::zeroed();
-        if x11::xlib::XGetWindowAttributes(display, focus_window, &mut window_attrs) == 0 {
-            x11::xlib::XCloseDisplay(display);
-            return false;
+        Detection { rule: matched_rule, ..base }
+    }
+}
+
+// Window rectangle (x, y, width, height) in root coordinates; None if the
+// window is gone
+fn window_rect(
+    display: *mut x11::xlib::Display,
+    root: x11::xlib::Window,
+    window: x11::xlib::Window,
+) -> Option<(i32, i32, i32, i32)> {
+    unsafe {
+        let mut attrs: x11::xlib::XWindowAttributes = std::mem::zeroed();
+        if x11::xlib::XGetWindowAttributes(display, window, &mut attrs) == 0 {
+            return None;
         }
+        let mut root_x = 0;
+        let mut root_y = 0;
+        let mut child_return: x11::xlib::Window = 0;
+        x11::xlib::XTranslateCoordinates(
+            display,
+            window,
+            root,
+            0,
+            0,
+            &mut root_x,
+            &mut root_y,
+            &mut child_return,
+        );
+        Some((root_x, root_y, attrs.width, attrs.height))
+    }
+}
+
+// Check the EWMH _NET_WM_STATE property for _NET_WM_STATE_FULLSCREEN
+fn is_ewmh_fullscreen(
+    display: *mut x11::xlib::Display,
+    atoms: &x11_watcher::Atoms,
+    window: x11::xlib::Window,
+) -> bool {
+    let wm_state = atoms.net_wm_state;
+    let fullscreen = atoms.net_wm_state_fullscreen;
+    if wm_state == 0 || fullscreen == 0 {
+        return false;
+    }
+    x11_watcher::read_property_u32s(display, window, wm_state)
+        .is_some_and(|states| states.contains(&fullscreen))
+}
 
-        // Check if window is fullscreen (covers entire screen)
-        let is_fullscreen =
-            window_attrs.width >= screen_width && window_attrs.height >= screen_height;
+// True when `window` (x, y, width, height) covers `monitor`, allowing each
+// edge to fall short by up to `tolerance` pixels
+fn rect_covers(window: (i32, i32, i32, i32), monitor: (i32, i32, i32, i32), tolerance: i32) -> bool {
+    let (wx, wy, ww, wh) = window;
+    let (mx, my, mw, mh) = monitor;
+    mw > 0
+        && mh > 0
+        && wx <= mx + tolerance
+        && wy <= my + tolerance
+        && wx + ww >= mx + mw - tolerance
+        && wy + wh >= my + mh - tolerance
+}
 
-        debug!("Focused={} window_size={}x{} screen_size={}x{} is_fullscreen={} cursor_hidden={} steam_present={}",
-            focus_window, window_attrs.width, window_attrs.height, screen_width, screen_height, is_fullscreen, cursor_hidden, steam_present);
+// Monitor/output rectangle: (x, y, width, height, scale)
+type MonitorRect = (i32, i32, i32, i32, i32);
+type OutputInfo = std::sync::Arc<std::sync::Mutex<Option<MonitorRect>>>;
 
-        // If fullscreen and cursor hidden, return true
-        if is_fullscreen && cursor_hidden {
-            x11::xlib::XCloseDisplay(display);
-            return true;
-        }
+// Logical output rectangle from xdg-output: (x, y, width, height)
+type LogicalInfo = std::sync::Arc<std::sync::Mutex<Option<(i32, i32, i32, i32)>>>;
 
-        x11::xlib::XCloseDisplay(display);
-        false
+// Output rectangle in root coordinates, with the mode size divided by the scale
+fn logical_rect((x, y, width, height, scale): MonitorRect) -> (i32, i32, i32, i32) {
+    let scale = scale.max(1);
+    (x, y, width / scale, height / scale)
+}
+
+// Monitors in a GNOME monitors.xml as logical (x, y, width, height): the
+// mode size is rotated to its on-screen orientation and divided by the
+// (possibly fractional) scale. The file keeps one <configuration> per set
+// of connected monitors; only the first is read.
+fn parse_monitors_xml(contents: &str) -> Vec<(i32, i32, i32, i32)> {
+    let lower = contents.to_lowercase();
+    // Fragments without a <configuration> are read whole
+    let configuration = lower
+        .find("<configuration>")
+        .and_then(|a| Some((a, lower[a..].find("</configuration>")? + a)))
+        .map_or(&lower[..], |(a, b)| &lower[a..b]);
+
+    let mut monitors = Vec::new();
+    let mut idx = 0usize;
+    while let Some(start) = configuration[idx..].find("<logicalmonitor>") {
+        let from = idx + start + "<logicalmonitor>".len();
+        let Some(end) = configuration[from..].find("</logicalmonitor>") else {
+            break;
+        };
+        let snippet = &configuration[from..from + end];
+        idx = from + end;
+        let extract_str = |tag: &str| -> Option<&str> {
+            let open = format!("<{}>", tag);
+            let close = format!("</{}>", tag);
+            let a = snippet.find(&open)? + open.len();
+            let b = snippet[a..].find(&close)? + a;
+            Some(snippet[a..b].trim())
+        };
+        let extract = |tag: &str| extract_str(tag)?.parse::<i32>().ok();
+        let x = extract("x").unwrap_or(0);
+        let y = extract("y").unwrap_or(0);
+        let width = extract("width").unwrap_or(1920);
+        let height = extract("height").unwrap_or(1080);
+        let scale = extract_str("scale")
+            .and_then(|scale| scale.parse::<f64>().ok())
+            .filter(|scale| *scale > 0.0)
+            .unwrap_or(1.0);
+        // Portrait monitors are stored with their unrotated mode size
+        let rotated = matches!(extract_str("rotation"), Some("left" | "right"));
+        let (width, height) = if rotated { (height, width) } else { (width, height) };
+        let logical = |size: i32| (f64::from(size) / scale).round() as i32;
+        monitors.push((x, y, logical(width), logical(height)));
+    }
+
+    monitors
+}
+
+// Quarter-turn transforms show the mode rotated, so width and height swap
+fn transform_swaps_axes(transform: wl_output::Transform) -> bool {
+    matches!(
+        transform,
+        wl_output::Transform::_90
+            | wl_output::Transform::_270
+            | wl_output::Transform::Flipped90
+            | wl_output::Transform::Flipped270
+    )
+}
+
+// Lock held or requested, as the debounce sees it
+#[derive(Debug, Clone, Copy)]
+struct LockState {
+    requested: bool,
+    // Client window the lock was requested for
+    window: Option<x11::xlib::Window>,
+}
+
+// What a detection pass should do with the lock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LockAction {
+    Keep,
+    Lock,
+    Unlock,
+    // Focus left the locked window: unlock without waiting for the debounce
+    UnlockNow,
+}
+
+// Debounce `detection` against the `current` lock: a decision differing
+// from it must hold for `delays` (lock, unlock) since it was first seen,
+// tracked in `pending`. Returns the action and the new pending decision.
+fn decide_lock(
+    detection: &Detection,
+    current: LockState,
+    pending: Option<(bool, std::time::Instant)>,
+    now: std::time::Instant,
+    (lock_delay, unlock_delay): (std::time::Duration, std::time::Duration),
+) -> (LockAction, Option<(bool, std::time::Instant)>) {
+    let focus_left = current.requested && current.window.is_some() && detection.window != current.window;
+    let requested = current.requested && !focus_left;
+    let settled = if focus_left { LockAction::UnlockNow } else { LockAction::Keep };
+    if detection.should_lock == requested {
+        // Decision matches current state; drop any pending transition
+        return (settled, None);
+    }
+    let since = match pending {
+        Some((lock, since)) if lock == detection.should_lock => since,
+        _ => now,
+    };
+    let delay = if detection.should_lock { lock_delay } else { unlock_delay };
+    if now.duration_since(since) < delay {
+        return (settled, Some((detection.should_lock, since)));
+    }
+    let action = if detection.should_lock { LockAction::Lock } else { LockAction::Unlock };
+    (action, None)
+}
+
+// A bound wl_output with the geometry it and its xdg_output reported
+struct Output {
+    output: wl_output::WlOutput,
+    info: OutputInfo,
+    transform: wl_output::Transform,
+    xdg_output: Option<zxdg_output_v1::ZxdgOutputV1>,
+    logical: LogicalInfo,
+}
+
+impl Output {
+    // Rectangle in the compositor's logical space, which XWayland uses too:
+    // xdg-output's when known (already transformed), else the wl_output mode
+    // rotated by the output transform and divided by the scale
+    fn rect(&self) -> Option<(i32, i32, i32, i32)> {
+        let logical = *self.logical.lock().ok()?;
+        if let Some(rect @ (_, _, width, height)) = logical {
+            if width > 0 && height > 0 {
+                return Some(rect);
+            }
+        }
+        let (x, y, width, height, scale) = (*self.info.lock().ok()?)?;
+        if transform_swaps_axes(self.transform) {
+            return Some(logical_rect((x, y, height, width, scale)));
+        }
+        Some(logical_rect((x, y, width, height, scale)))
     }
 }
 
+// A fresh compositor connection with its bound globals and event queue
+type WaylandSession = (Connection, AppData, wayland_client::EventQueue<AppData>);
+
+// Virtual pointer with the (position, extent) passed to motion_absolute
+type VirtualPointerTarget = (zwlr_virtual_pointer_v1::ZwlrVirtualPointerV1, (u32, u32), (u32, u32));
+
 struct AppData {
     pointer_constraints: Option<zwp_pointer_constraints_v1::ZwpPointerConstraintsV1>,
     seat: Option<wl_seat::WlSeat>,
     pointer: Option<wl_pointer::WlPointer>,
+    keyboard: Option<wl_keyboard::WlKeyboard>,
     compositor: Option<wl_compositor::WlCompositor>,
-    surface: Option<wl_surface::WlSurface>,
-    shell: Option<wl_shell::WlShell>,
+    wm_base: Option<xdg_wm_base::XdgWmBase>,
+    layer_shell: Option<zwlr_layer_shell_v1::ZwlrLayerShellV1>,
+    shm: Option<wl_shm::WlShm>,
+    viewporter: Option<wp_viewporter::WpViewporter>,
+    relative_pointer_manager: Option<zwp_relative_pointer_manager_v1::ZwpRelativePointerManagerV1>,
+    pointer_warp: Option<wp_pointer_warp_v1::WpPointerWarpV1>,
+    virtual_pointer_manager: Option<zwlr_virtual_pointer_manager_v1::ZwlrVirtualPointerManagerV1>,
+    // Serial of the last wl_pointer.enter, while the pointer is on one of our surfaces
+    pointer_enter_serial: Option<u32>,
+    // Delivers motion deltas while the pointer is locked, forwarded through XTest
+    relative_pointer: Option<zwp_relative_pointer_v1::ZwpRelativePointerV1>,
+    motion_forwarder: Option<MotionForwarder>,
+    // Root position of the lock surface, to map confined motion back to X
+    lock_origin: (i32, i32),
+    // Surface the pointer is locked to; mapped while a lock is requested
+    lock_surface: Option<LockSurface>,
+    // Transparent 1x1 buffer attached to the lock surface, reused across locks
+    lock_buffer: Option<wl_buffer::WlBuffer>,
+    // The game's X cursor, shown while the pointer is on the lock surface
+    game_cursor: Option<GameCursor>,
     locked_pointer: Option<zwp_locked_pointer_v1::ZwpLockedPointerV1>,
-    // Set to true when compositor acknowledges the locked pointer via Locked event
+    confined_pointer: Option<zwp_confined_pointer_v1::ZwpConfinedPointerV1>,
+    // Set to true when compositor acknowledges the constraint via Locked/Confined event
     locked_acquired: bool,
-    // Per-output info: (wl_output, Arc<Mutex<Option<(x,y,width,height,scale)>>>)
-    outputs: Vec<(wl_output::WlOutput, std::sync::Arc<std::sync::Mutex<Option<(i32, i32, i32, i32, i32)>>>)>,
+    xdg_output_manager: Option<zxdg_output_manager_v1::ZxdgOutputManagerV1>,
+    outputs: Vec<Output>,
+    // Advertised globals by name: (interface, version)
+    globals: std::collections::HashMap<u32, (String, u32)>,
+    // Set when outputs are added, removed or reconfigured
+    outputs_changed: bool,
+    // Set when a held lock/confinement was dropped because its seat or global went away
+    constraints_lost: bool,
 }
 
-impl Dispatch<wl_registry::WlRegistry, ()> for AppData {
-    fn event(
-        state: &mut Self,
+impl AppData {
+    // Bind a global the daemon uses and keep its proxy
+    fn bind_global(
+        &mut self,
         registry: &wl_registry::WlRegistry,
-        event: wl_registry::Event,
-        _: &(),
-        _: &Connection,
+        name: u32,
+        interface: &str,
+        version: u32,
         qh: &QueueHandle<AppData>,
     ) {
-        if let wl_registry::Event::Global {
-            name, interface, ..
-        } = event
-        {
-            match &interface[..] {
-                "zwp_pointer_constraints_v1" => {
-                    let pointer_constraints = registry
-                        .bind::<zwp_pointer_constraints_v1::ZwpPointerConstraintsV1, _, _>(
+        match interface {
+            "zwp_pointer_constraints_v1" => {
+                let pointer_constraints = registry
+                    .bind::<zwp_pointer_constraints_v1::ZwpPointerConstraintsV1, _, _>(
+                    name,
+                    1,
+                    qh,
+                    (),
+                );
+                debug!("Bound pointer constraints interface");
+                self.pointer_constraints = Some(pointer_constraints);
+            }
+            "wl_seat" => {
+                // Only one seat is used; others are bound if it goes away
+                if self.seat.is_some() {
+                    return;
+                }
+                let seat = registry.bind::<wl_seat::WlSeat, _, _>(name, version.min(5), qh, name);
+                debug!("Bound seat interface - requesting capabilities...");
+                self.seat = Some(seat);
+            }
+            "wl_compositor" => {
+                let compositor =
+                    registry.bind::<wl_compositor::WlCompositor, _, _>(name, 4, qh, ());
+                debug!("Bound compositor interface");
+                self.compositor = Some(compositor);
+            }
+            "wl_output" => {
+                // Bind wl_output and keep the object and an associated info slot to receive events
+                let output = registry.bind::<wl_output::WlOutput, _, _>(name, 3, qh, name);
+                let info = std::sync::Arc::new(std::sync::Mutex::new(None));
+                let logical: LogicalInfo = std::sync::Arc::new(std::sync::Mutex::new(None));
+                let xdg_output = self
+                    .xdg_output_manager
+                    .as_ref()
+                    .map(|manager| manager.get_xdg_output(&output, qh, logical.clone()));
+                debug!("Bound wl_output interface");
+                self.outputs.push(Output {
+                    output,
+                    info,
+                    transform: wl_output::Transform::Normal,
+                    xdg_output,
+                    logical,
+                });
+                self.outputs_changed = true;
+            }
+            "zxdg_output_manager_v1" => {
+                let manager = registry.bind::<zxdg_output_manager_v1::ZxdgOutputManagerV1, _, _>(
+                    name,
+                    version.min(3),
+                    qh,
+                    (),
+                );
+                debug!("Bound xdg output manager interface (version {})", version.min(3));
+                // Outputs bound before the manager get their xdg_output now
+                for output in self.outputs.iter_mut().filter(|o| o.xdg_output.is_none()) {
+                    output.xdg_output = Some(manager.get_xdg_output(&output.output, qh, output.logical.clone()));
+                }
+                self.xdg_output_manager = Some(manager);
+            }
+            "zwlr_layer_shell_v1" => {
+                let layer_shell = registry.bind::<zwlr_layer_shell_v1::ZwlrLayerShellV1, _, _>(
+                    name,
+                    version.min(4),
+                    qh,
+                    (),
+                );
+                debug!("Bound layer shell interface (version {})", version.min(4));
+                self.layer_shell = Some(layer_shell);
+            }
+            "wl_shm" => {
+                let shm = registry.bind::<wl_shm::WlShm, _, _>(name, 1, qh, ());
+                debug!("Bound shm interface");
+                self.shm = Some(shm);
+            }
+            "wp_viewporter" => {
+                let viewporter = registry.bind::<wp_viewporter::WpViewporter, _, _>(name, 1, qh, ());
+                debug!("Bound viewporter interface");
+                self.viewporter = Some(viewporter);
+            }
+            "xdg_wm_base" => {
+                let wm_base = registry.bind::<xdg_wm_base::XdgWmBase, _, _>(name, 1, qh, ());
+                debug!("Bound xdg_wm_base interface");
+                self.wm_base = Some(wm_base);
+            }
+            "wp_pointer_warp_v1" => {
+                let pointer_warp =
+                    registry.bind::<wp_pointer_warp_v1::WpPointerWarpV1, _, _>(name, 1, qh, ());
+                debug!("Bound pointer warp interface");
+                self.pointer_warp = Some(pointer_warp);
+            }
+            "zwlr_virtual_pointer_manager_v1" => {
+                let manager = registry
+                    .bind::<zwlr_virtual_pointer_manager_v1::ZwlrVirtualPointerManagerV1, _, _>(
+                        name,
+                        version.min(2),
+                        qh,
+                        (),
+                    );
+                debug!("Bound virtual pointer manager interface (version {})", version.min(2));
+                self.virtual_pointer_manager = Some(manager);
+            }
+            "zwp_relative_pointer_manager_v1" => {
+                let manager = registry
+                    .bind::<zwp_relative_pointer_manager_v1::ZwpRelativePointerManagerV1, _, _>(
                         name,
                         1,
                         qh,
                         (),
                     );
-                    debug!("Bound pointer constraints interface");
-                    state.pointer_constraints = Some(pointer_constraints);
-                }
-                "wl_seat" => {
-                    let seat = registry.bind::<wl_seat::WlSeat, _, _>(name, 1, qh, ());
-                    debug!("Bound seat interface - requesting capabilities...");
-                    state.seat = Some(seat);
+                debug!("Bound relative pointer manager interface");
+                self.relative_pointer_manager = Some(manager);
+            }
+            _ => {}
+        }
+    }
+
+    // A global went away (monitor unplugged, seat removed, ...): drop the
+    // objects bound to it and fall back to another global of the same kind
+    fn remove_global(&mut self, registry: &wl_registry::WlRegistry, name: u32, qh: &QueueHandle<AppData>) {
+        let Some((interface, _)) = self.globals.remove(&name) else {
+            return;
+        };
+        debug!("Global {} ({}) removed", name, interface);
+        match &interface[..] {
+            "wl_output" => {
+                if let Some(index) = self.outputs.iter().position(|o| o.output.data::<u32>() == Some(&name)) {
+                    let output = self.outputs.remove(index);
+                    if let Some(xdg_output) = output.xdg_output {
+                        xdg_output.destroy();
+                    }
+                    output.output.release();
+                    info!("Output removed");
+                    self.outputs_changed = true;
                 }
-                "wl_compositor" => {
-                    let compositor =
-                        registry.bind::<wl_compositor::WlCompositor, _, _>(name, 4, qh, ());
-                    debug!("Bound compositor interface");
-                    state.compositor = Some(compositor);
+                return;
+            }
+            "wl_seat" => {
+                if self.seat.as_ref().is_none_or(|seat| seat.data::<u32>() != Some(&name)) {
+                    return;
                 }
-                "wl_output" => {
-                    // Bind wl_output and keep the object and an associated info slot to receive events
-                    let output = registry.bind::<wl_output::WlOutput, _, _>(name, 3, qh, ());
-                    let info = std::sync::Arc::new(std::sync::Mutex::new(None));
-                    debug!("Bound wl_output interface");
-                    state.outputs.push((output, info));
+                info!("Seat removed, dropping its pointer and keyboard");
+                self.drop_pointer();
+                self.drop_keyboard();
+                if let Some(seat) = self.seat.take() {
+                    if seat.version() >= 5 {
+                        seat.release();
+                    }
                 }
-                "wl_shell" => {
-                    let shell = registry.bind::<wl_shell::WlShell, _, _>(name, 1, qh, ());
-                    debug!("Bound shell interface");
-                    state.shell = Some(shell);
+            }
+            "zwp_pointer_constraints_v1" => {
+                warn!("Pointer constraints global removed");
+                self.drop_constraints();
+                self.pointer_constraints = None;
+            }
+            "wl_compositor" => {
+                // Our surfaces went with it, and the constraint is useless without its surface
+                self.drop_constraints();
+                self.destroy_lock_surface();
+                self.compositor = None;
+            }
+            "zwlr_layer_shell_v1" => self.layer_shell = None,
+            "xdg_wm_base" => self.wm_base = None,
+            "wl_shm" => self.shm = None,
+            "wp_viewporter" => self.viewporter = None,
+            "zxdg_output_manager_v1" => {
+                // Logical geometry is stale without updates; fall back to wl_output's
+                for output in &mut self.outputs {
+                    if let Some(xdg_output) = output.xdg_output.take() {
+                        xdg_output.destroy();
+                    }
+                    if let Ok(mut logical) = output.logical.lock() {
+                        *logical = None;
+                    }
                 }
-                _ => {}
+                self.outputs_changed = true;
+                self.xdg_output_manager = None;
+            }
+            "wp_pointer_warp_v1" => self.pointer_warp = None,
+            "zwlr_virtual_pointer_manager_v1" => self.virtual_pointer_manager = None,
+            "zwp_relative_pointer_manager_v1" => {
+                self.stop_relative_motion();
+                self.relative_pointer_manager = None;
             }
+            _ => return,
+        }
+        // Rebind a remaining global of the same interface, e.g. a second seat
+        let remaining = self
+            .globals
+            .iter()
+            .find(|(_, (other, _))| *other == interface)
+            .map(|(&other, &(_, version))| (other, version));
+        if let Some((other, version)) = remaining {
+            debug!("Rebinding {} from global {}", interface, other);
+            self.bind_global(registry, other, &interface, version, qh);
         }
     }
-}
 
-// Implement required trait dispatches
-impl Dispatch<zwp_pointer_constraints_v1::ZwpPointerConstraintsV1, ()> for AppData {
-    fn event(
-        _: &mut Self,
-        _: &zwp_pointer_constraints_v1::ZwpPointerConstraintsV1,
-        _: zwp_pointer_constraints_v1::Event,
-        _: &(),
-        _: &Connection,
-        _: &QueueHandle<AppData>,
-    ) {
+    // Release the wl_pointer and everything created from it
+    fn drop_pointer(&mut self) {
+        self.drop_constraints();
+        if let Some(pointer) = self.pointer.take() {
+            if pointer.version() >= 3 {
+                pointer.release();
+            }
+        }
+        self.pointer_enter_serial = None;
+    }
+
+    // Release the wl_keyboard and any keys forwarded through it
+    fn drop_keyboard(&mut self) {
+        if let Some(keyboard) = self.keyboard.take() {
+            if keyboard.version() >= 3 {
+                keyboard.release();
+            }
+        }
+        if let Some(forwarder) = &mut self.motion_forwarder {
+            forwarder.release_keys();
+        }
+    }
+
+    // Record keyboard focus entering or leaving `surface` when it is our
+    // fullscreen toplevel, the only lock surface that takes keyboard focus
+    fn set_keyboard_focus(&mut self, surface: &wl_surface::WlSurface, focused: bool) {
+        if let Some(LockSurface::Xdg { surface: lock_surface, keyboard_focus, .. }) = &mut self.lock_surface {
+            if lock_surface == surface {
+                *keyboard_focus = Some(focused);
+            }
+        }
+    }
+
+    // Our toplevel has keyboard focus, so keys meant for the game reach us
+    fn lock_surface_focused(&self) -> bool {
+        matches!(self.lock_surface, Some(LockSurface::Xdg { keyboard_focus: Some(true), .. }))
+    }
+
+    // Mapping our toplevel moves focus off the game, which X reports as no
+    // active window. That is expected while the toplevel has keyboard focus
+    // or, before the constraint is active, has not received it yet.
+    fn lock_surface_took_focus(&self) -> bool {
+        match &self.lock_surface {
+            Some(LockSurface::Xdg { keyboard_focus, .. }) => {
+                keyboard_focus.unwrap_or(!self.constraint_active())
+            }
+            _ => false,
+        }
+    }
+
+    // Destroy the locked/confined pointer; a held constraint is reported
+    // through `constraints_lost` so the daemon can lock again
+    fn drop_constraints(&mut self) {
+        self.stop_relative_motion();
+        if let Some(locked_pointer) = self.locked_pointer.take() {
+            locked_pointer.destroy();
+            self.constraints_lost = true;
+        }
+        if let Some(confined_pointer) = self.confined_pointer.take() {
+            confined_pointer.destroy();
+            self.constraints_lost = true;
+        }
+        self.locked_acquired = false;
+    }
+
+    // Unmap and destroy the lock surface and the cursor shown over it; the
+    // pointer can't be on our surface any more, so its enter serial is stale
+    fn destroy_lock_surface(&mut self) {
+        self.pointer_enter_serial = None;
+        if let Some(lock_surface) = self.lock_surface.take() {
+            lock_surface.destroy();
+        }
+        if let Some(game_cursor) = self.game_cursor.take() {
+            game_cursor.destroy();
+        }
+    }
+
+    // The compositor acknowledged the lock or confinement and still holds it
+    fn constraint_active(&self) -> bool {
+        self.locked_acquired && (self.locked_pointer.is_some() || self.confined_pointer.is_some())
+    }
+
+    // Stop receiving relative motion and close the XTest connection
+    fn stop_relative_motion(&mut self) {
+        if let Some(relative_pointer) = self.relative_pointer.take() {
+            relative_pointer.destroy();
+        }
+        self.motion_forwarder = None;
     }
 }
 
-impl Dispatch<wl_compositor::WlCompositor, ()> for AppData {
+impl Dispatch<wl_registry::WlRegistry, ()> for AppData {
     fn event(
-        _: &mut Self,
-        _: &wl_compositor::WlCompositor,
-        _: wl_compositor::Event,
+        state: &mut Self,
+        registry: &wl_registry::WlRegistry,
+        event: wl_registry::Event,
         _: &(),
         _: &Connection,
-        _: &QueueHandle<AppData>,
+        qh: &QueueHandle<AppData>,
     ) {
+        match event {
+            wl_registry::Event::Global {
+                name, interface, version,
+            } => {
+                state.globals.insert(name, (interface.clone(), version));
+                state.bind_global(registry, name, &interface, version, qh);
+            }
+            wl_registry::Event::GlobalRemove { name } => {
+                state.remove_global(registry, name, qh);
+            }
+            _ => {}
+        }
     }
 }
 
-impl Dispatch<wl_shell::WlShell, ()> for AppData {
+// Implement required trait dispatches
+impl Dispatch<zwp_pointer_constraints_v1::ZwpPointerConstraintsV1, ()> for AppData {
     fn event(
         _: &mut Self,
-        _: &wl_shell::WlShell,
-        _: wl_shell::Event,
+        _: &zwp_pointer_constraints_v1::ZwpPointerConstraintsV1,
+        _: zwp_pointer_constraints_v1::Event,
         _: &(),
         _: &Connection,
         _: &QueueHandle<AppData>,
@@ -242,11 +772,11 @@ impl Dispatch<wl_shell::WlShell, ()> for AppData {
     }
 }
 
-impl Dispatch<wl_shell_surface::WlShellSurface, ()> for AppData {
+impl Dispatch<wl_compositor::WlCompositor, ()> for AppData {
     fn event(
         _: &mut Self,
-        _: &wl_shell_surface::WlShellSurface,
-        _: wl_shell_surface::Event,
+        _: &wl_compositor::WlCompositor,
+        _: wl_compositor::Event,
         _: &(),
         _: &Connection,
         _: &QueueHandle<AppData>,
@@ -266,12 +796,12 @@ impl Dispatch<wl_surface::WlSurface, ()> for AppData {
     }
 }
 
-impl Dispatch<wl_seat::WlSeat, ()> for AppData {
+impl Dispatch<wl_seat::WlSeat, u32> for AppData {
     fn event(
         state: &mut Self,
         seat: &wl_seat::WlSeat,
         event: wl_seat::Event,
-        _: &(),
+        _: &u32,
         _: &Connection,
         qh: &QueueHandle<AppData>,
     ) {
@@ -287,52 +817,67 @@ impl Dispatch<wl_seat::WlSeat, ()> for AppData {
                     caps_value, pointer_bit
                 );
 
-            if (caps_value & pointer_bit) != 0 {
+            let has_pointer = (caps_value & pointer_bit) != 0;
+            if has_pointer && state.pointer.is_none() {
                 let pointer = seat.get_pointer(qh, ());
                 info!("Got pointer capability and created pointer device");
                 state.pointer = Some(pointer);
-            } else {
+            } else if !has_pointer && state.pointer.is_some() {
+                info!("Pointer capability removed, dropping pointer device");
+                state.drop_pointer();
+            } else if !has_pointer {
                 warn!("No pointer capability available");
             }
+
+            // Keys only reach us while our fullscreen toplevel has keyboard focus
+            let has_keyboard = (caps_value & u32::from(wl_seat::Capability::Keyboard)) != 0;
+            if has_keyboard && state.keyboard.is_none() {
+                debug!("Got keyboard capability and created keyboard device");
+                state.keyboard = Some(seat.get_keyboard(qh, ()));
+            } else if !has_keyboard && state.keyboard.is_some() {
+                debug!("Keyboard capability removed, dropping keyboard device");
+                state.drop_keyboard();
+            }
         }
     }
 }
 
-impl Dispatch<wl_output::WlOutput, ()> for AppData {
+impl Dispatch<wl_output::WlOutput, u32> for AppData {
     fn event(
         state: &mut Self,
         _output: &wl_output::WlOutput,
         event: wl_output::Event,
-        _: &(),
+        _: &u32,
         _: &Connection,
         _: &QueueHandle<AppData>,
     ) {
         // Find the matching stored output and update its info
-        for (stored_output, info_arc) in &state.outputs {
-            if stored_output == _output {
-                if let Ok(mut guard) = info_arc.lock() {
+        for stored in &mut state.outputs {
+            if &stored.output == _output {
+                if let Ok(mut guard) = stored.info.lock() {
                     match event {
-                        wl_output::Event::Geometry { x, y, physical_width: _, physical_height: _, subpixel: _, make: _, model: _, transform: _ } => {
-                            // store x,y (position); other fields handled elsewhere
+                        wl_output::Event::Geometry { x, y, physical_width: _, physical_height: _, subpixel: _, make: _, model: _, transform } => {
+                            // store x,y (position) and the rotation; other fields handled elsewhere
                             let (w, h, scale) = {
                                 let t = guard.unwrap_or((0, 0, 0, 0, 1));
                                 (t.2, t.3, t.4)
                             };
-                            *guard = Some((x as i32, y as i32, w, h, scale));
-                            debug!("wl_output geometry: x={} y={}", x, y);
+                            *guard = Some((x, y, w, h, scale));
+                            stored.transform = transform.into_result().unwrap_or(wl_output::Transform::Normal);
+                            debug!("wl_output geometry: x={} y={} transform={:?}", x, y, stored.transform);
                         }
                         wl_output::Event::Mode { flags: _, width, height, refresh: _ } => {
                             let (x, y, _w, _h, scale) = guard.unwrap_or((0, 0, 0, 0, 1));
-                            *guard = Some((x, y, width as i32, height as i32, scale));
+                            *guard = Some((x, y, width, height, scale));
                             debug!("wl_output mode: {}x{} (stored pos {}x{}) scale={}", width, height, x, y, scale);
                         }
                         wl_output::Event::Scale { factor } => {
                             let (x, y, w, h, _old_scale) = guard.unwrap_or((0, 0, 0, 0, 1));
-                            *guard = Some((x, y, w, h, factor as i32));
+                            *guard = Some((x, y, w, h, factor));
                             debug!("wl_output scale event: factor={}", factor);
                         }
                         wl_output::Event::Done => {
-                            // nothing special
+                            state.outputs_changed = true;
                         }
                         _ => {}
                     }
@@ -343,11 +888,11 @@ impl Dispatch<wl_output::WlOutput, ()> for AppData {
     }
 }
 
-impl Dispatch<wl_pointer::WlPointer, ()> for AppData {
+impl Dispatch<zxdg_output_manager_v1::ZxdgOutputManagerV1, ()> for AppData {
     fn event(
         _: &mut Self,
-        _: &wl_pointer::WlPointer,
-        _: wl_pointer::Event,
+        _: &zxdg_output_manager_v1::ZxdgOutputManagerV1,
+        _: zxdg_output_manager_v1::Event,
         _: &(),
         _: &Connection,
         _: &QueueHandle<AppData>,
@@ -355,394 +900,757 @@ impl Dispatch<wl_pointer::WlPointer, ()> for AppData {
     }
 }
 
-impl Dispatch<zwp_locked_pointer_v1::ZwpLockedPointerV1, ()> for AppData {
+impl Dispatch<zxdg_output_v1::ZxdgOutputV1, LogicalInfo> for AppData {
     fn event(
         state: &mut Self,
-        _: &zwp_locked_pointer_v1::ZwpLockedPointerV1,
-        event: zwp_locked_pointer_v1::Event,
-        _: &(),
+        _: &zxdg_output_v1::ZxdgOutputV1,
+        event: zxdg_output_v1::Event,
+        logical: &LogicalInfo,
         _: &Connection,
         _: &QueueHandle<AppData>,
     ) {
+        let Ok(mut guard) = logical.lock() else {
+            return;
+        };
+        let (x, y, width, height) = guard.unwrap_or((0, 0, 0, 0));
         match event {
-            zwp_locked_pointer_v1::Event::Locked => {
-                info!("🔒 Pointer successfully locked!");
-                state.locked_acquired = true;
+            zxdg_output_v1::Event::LogicalPosition { x, y } => {
+                *guard = Some((x, y, width, height));
+                debug!("xdg_output logical position: x={} y={}", x, y);
             }
-            zwp_locked_pointer_v1::Event::Unlocked => {
-                info!("🔓 Pointer unlocked");
-                // Clear the locked pointer when it's unlocked
-                state.locked_pointer = None;
-                state.locked_acquired = false;
+            zxdg_output_v1::Event::LogicalSize { width, height } => {
+                *guard = Some((x, y, width, height));
+                debug!("xdg_output logical size: {}x{}", width, height);
+            }
+            // Only sent before version 3; later versions finish with wl_output.done
+            zxdg_output_v1::Event::Done => {
+                state.outputs_changed = true;
             }
             _ => {}
         }
     }
 }
 
-struct PointerLockDaemon {
-    app_data: Option<AppData>,
-    event_queue: Option<wayland_client::EventQueue<AppData>>,
-    is_locked: bool, // Track current lock state
-    warp_thread: Option<std::thread::JoinHandle<()>>, // Thread for warping cursor
-    warp_stop: Option<std::sync::Arc<std::sync::atomic::AtomicBool>>, // Signal to stop warping
-}
-
-impl PointerLockDaemon {
-    fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        // Try to connect to Wayland
-        match Connection::connect_to_env() {
-            Ok(conn) => {
-                info!("Connected to Wayland display");
-
-                let mut app_data = AppData {
-                    pointer_constraints: None,
-                    seat: None,
-                    pointer: None,
-                    compositor: None,
-                    surface: None,
-                    shell: None,
-                    locked_pointer: None,
-                    locked_acquired: false,
-                    outputs: Vec::new(),
-                };
-
-                let display = conn.display();
-                let mut event_queue = conn.new_event_queue();
-                let qh = event_queue.handle();
-
-                // Get the registry and bind to global objects
-                let _registry = display.get_registry(&qh, ());
-
-                // First roundtrip to get all globals
-                event_queue.blocking_dispatch(&mut app_data)?;
-
-                // Second roundtrip to get seat capabilities after binding
-                if app_data.seat.is_some() {
-                    debug!("Doing second roundtrip to get seat capabilities...");
-                    event_queue.blocking_dispatch(&mut app_data)?;
+impl Dispatch<wl_pointer::WlPointer, ()> for AppData {
+    fn event(
+        state: &mut Self,
+        pointer: &wl_pointer::WlPointer,
+        event: wl_pointer::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<AppData>,
+    ) {
+        // Pointer warps must quote the serial of the enter on our surface
+        match event {
+            wl_pointer::Event::Enter { serial, surface, .. } => {
+                state.pointer_enter_serial = Some(serial);
+                // Our surface covers the game: show its cursor, not the compositor's
+                let on_lock_surface = state.lock_surface.as_ref().is_some_and(|l| l.surface() == &surface);
+                if let (true, Some(game_cursor)) = (on_lock_surface, &state.game_cursor) {
+                    game_cursor.apply(pointer, serial);
                 }
-
-                // Create surface even without shell - just a basic surface for pointer locking
-                if let Some(compositor) = &app_data.compositor {
-                    let surface = compositor.create_surface(&qh, ());
-                    debug!("Created basic surface for pointer locking");
-
-                    // Commit the surface to make it "live"
-                    surface.commit();
-
-                    app_data.surface = Some(surface);
-
-                    debug!("Surface committed and ready for pointer locking");
-                } else {
-                    warn!("Warning: Missing compositor, cannot create surface");
+            }
+            wl_pointer::Event::Leave { .. } => {
+                state.pointer_enter_serial = None;
+            }
+            // Our surface sits on top of the game from the lock request until
+            // unlock, including while the compositor hasn't activated the
+            // constraint or has deactivated it. The cursor then moves over
+            // our surface rather than the game's (a locked pointer reports no
+            // motion), and clicks and scrolling land on it; hand them all on
+            // to XWayland so the game never goes without input.
+            wl_pointer::Event::Motion { surface_x, surface_y, .. } if state.lock_surface.is_some() => {
+                let (x, y) = state.lock_origin;
+                if let Some(forwarder) = &mut state.motion_forwarder {
+                    forwarder.move_to(f64::from(x) + surface_x, f64::from(y) + surface_y);
                 }
-
-                info!("Wayland protocols initialized successfully");
-
-                Ok(PointerLockDaemon {
-                    app_data: Some(app_data),
-                    event_queue: Some(event_queue),
-                    is_locked: false,
-                    warp_thread: None,
-                    warp_stop: None,
-                })
             }
-            Err(e) => {
-                println!(
-                    "Failed to connect to Wayland: {}, running without pointer constraints",
-                    e
-                );
-                Ok(PointerLockDaemon {
-                    app_data: None,
-                    event_queue: None,
-                    is_locked: false,
-                    warp_thread: None,
-                    warp_stop: None,
-                })
+            wl_pointer::Event::Button { button, state: button_state, .. } if state.lock_surface.is_some() => {
+                let pressed = matches!(button_state.into_result(), Ok(wl_pointer::ButtonState::Pressed));
+                if let Some(forwarder) = &mut state.motion_forwarder {
+                    forwarder.button(button, pressed);
+                }
             }
+            wl_pointer::Event::Axis { axis, value, .. } if state.lock_surface.is_some() => {
+                let horizontal = matches!(axis.into_result(), Ok(wl_pointer::Axis::HorizontalScroll));
+                if let Some(forwarder) = &mut state.motion_forwarder {
+                    forwarder.axis(horizontal, value);
+                }
+            }
+            _ => {}
         }
     }
+}
 
-    fn should_lock(&self) -> bool {
-        should_lock_pointer()
+// Marks a roundtrip done when the compositor answers its sync request
+impl Dispatch<wl_callback::WlCallback, std::sync::Arc<std::sync::atomic::AtomicBool>> for AppData {
+    fn event(
+        _: &mut Self,
+        _: &wl_callback::WlCallback,
+        event: wl_callback::Event,
+        done: &std::sync::Arc<std::sync::atomic::AtomicBool>,
+        _: &Connection,
+        _: &QueueHandle<AppData>,
+    ) {
+        if let wl_callback::Event::Done { .. } = event {
+            done.store(true, std::sync::atomic::Ordering::Relaxed);
+        }
     }
+}
 
-    fn get_wayland_surface_center(&self) -> Option<(i32, i32)> {
-        if let Some(app_data) = &self.app_data {
-            if let Some(_surface) = &app_data.surface {
-                // 1) Try parsing GNOME monitors.xml into monitor list
-                if let Some(monitors) = Self::parse_gnome_monitors_list() {
-                    debug!("parse_gnome_monitors_list returned {} monitors", monitors.len());
-                    for (i, m) in monitors.iter().enumerate() {
-                        debug!("monitor[{}] = x={} y={} w={} h={} scale={}", i, m.0, m.1, m.2, m.3, m.4);
+impl Dispatch<wl_keyboard::WlKeyboard, ()> for AppData {
+    fn event(
+        state: &mut Self,
+        _: &wl_keyboard::WlKeyboard,
+        event: wl_keyboard::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<AppData>,
+    ) {
+        // XWayland keeps the keymap, so keys are forwarded as raw keycodes
+        match event {
+            wl_keyboard::Event::Enter { surface, .. } => {
+                state.set_keyboard_focus(&surface, true);
+                if state.lock_surface_focused() {
+                    debug!("Lock surface took keyboard focus, forwarding keys to the game");
+                    if let Some(forwarder) = &state.motion_forwarder {
+                        forwarder.focus_window();
                     }
+                }
+            }
+            wl_keyboard::Event::Leave { surface, .. } => {
+                state.set_keyboard_focus(&surface, false);
+                if let Some(forwarder) = &mut state.motion_forwarder {
+                    forwarder.release_keys();
+                }
+            }
+            wl_keyboard::Event::Key { key, state: key_state, .. } if state.lock_surface_focused() => {
+                let pressed = matches!(key_state.into_result(), Ok(wl_keyboard::KeyState::Pressed));
+                if let Some(forwarder) = &mut state.motion_forwarder {
+                    forwarder.key(key, pressed);
+                }
+            }
+            _ => {}
+        }
+    }
+}
 
-                    // If only one monitor, use its center
-                    if monitors.len() == 1 {
-                        let (x, y, w, h, scale) = monitors[0];
-                        debug!("Single monitor detected, selecting center");
-                        let sw = if scale == 0 { w } else { w / scale };
-                        let sh = if scale == 0 { h } else { h / scale };
-                        return Some(((sw) / 2 + x, (sh) / 2 + y));
-                    }
+impl Dispatch<wp_pointer_warp_v1::WpPointerWarpV1, ()> for AppData {
+    fn event(
+        _: &mut Self,
+        _: &wp_pointer_warp_v1::WpPointerWarpV1,
+        _: wp_pointer_warp_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<AppData>,
+    ) {
+    }
+}
 
-                    // If multiple monitors, try to get focused X11 window center and pick containing monitor
-                    if let Some((fx, fy)) = Self::get_focused_x11_window_center() {
-                        debug!("Focused X11 window center at {}x{}", fx, fy);
-                        for (mx, my, mw, mh, scale) in &monitors {
-                            let rx = *mx;
-                            let ry = *my;
-                            let rw = if *scale == 0 { *mw } else { *mw / *scale };
-                            let rh = if *scale == 0 { *mh } else { *mh / *scale };
-                            let contains = fx >= rx && fx < rx + rw && fy >= ry && fy < ry + rh;
-                            debug!("testing monitor rect x={} y={} w={} h={} contains={}", rx, ry, rw, rh, contains);
-                            if contains {
-                                debug!("Selected monitor containing focused point: x={} y={} w={} h={} scale={}", rx, ry, rw, rh, scale);
-                                return Some(((rw) / 2 + rx, (rh) / 2 + ry));
-                            }
-                        }
-                    } else {
-                        debug!("No focused X11 window center available to choose monitor");
-                    }
+impl Dispatch<zwp_locked_pointer_v1::ZwpLockedPointerV1, ()> for AppData {
+    fn event(
+        state: &mut Self,
+        _: &zwp_locked_pointer_v1::ZwpLockedPointerV1,
+        event: zwp_locked_pointer_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<AppData>,
+    ) {
+        match event {
+            zwp_locked_pointer_v1::Event::Locked => {
+                info!("🔒 Pointer successfully locked!");
+                state.locked_acquired = true;
+            }
+            zwp_locked_pointer_v1::Event::Unlocked => {
+                // The lock is persistent: the compositor activates it again
+                // when our surface regains pointer focus
+                info!("🔓 Pointer unlocked by the compositor");
+                state.locked_acquired = false;
+            }
+            _ => {}
+        }
+    }
+}
 
-                    // Fallback: use primary monitor (first)
-                    let (x, y, w, h, scale) = monitors[0];
-                    debug!("Falling back to primary monitor from monitors.xml");
-                    let sw = if scale == 0 { w } else { w / scale };
-                    let sh = if scale == 0 { h } else { h / scale };
-                    return Some(((sw) / 2 + x, (sh) / 2 + y));
-                }
+impl Dispatch<zwlr_virtual_pointer_manager_v1::ZwlrVirtualPointerManagerV1, ()> for AppData {
+    fn event(
+        _: &mut Self,
+        _: &zwlr_virtual_pointer_manager_v1::ZwlrVirtualPointerManagerV1,
+        _: zwlr_virtual_pointer_manager_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<AppData>,
+    ) {
+    }
+}
 
-                // 2) Prefer Wayland per-output info collected earlier
-                if !app_data.outputs.is_empty() {
-                    // Try to use focused X11 point to select the right output
-                    if let Some((fx, fy)) = Self::get_focused_x11_window_center() {
-                        for (_out, info_arc) in &app_data.outputs {
-                            if let Ok(guard) = info_arc.lock() {
-                                if let Some((ox, oy, ow, oh, scale)) = *guard {
-                                    let rw = if scale == 0 { ow } else { ow / scale };
-                                    let rh = if scale == 0 { oh } else { oh / scale };
-                                    let contains = fx >= ox && fx < ox + rw && fy >= oy && fy < oy + rh;
-                                    debug!("testing stored output x={} y={} w={} h={} scale={} contains={}", ox, oy, rw, rh, scale, contains);
-                                    if contains {
-                                        let center_x = ox + rw / 2;
-                                        let center_y = oy + rh / 2;
-                                        debug!("Selected stored output center {}x{}", center_x, center_y);
-                                        return Some((center_x, center_y));
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    // otherwise fallback to first output's center
-                    if let Some((_out, info_arc)) = app_data.outputs.get(0) {
-                        if let Ok(guard) = info_arc.lock() {
-                                if let Some((ox, oy, ow, oh, scale)) = *guard {
-                                let adjw = if scale == 0 { ow } else { ow / scale };
-                                let adjh = if scale == 0 { oh } else { oh / scale };
-                                let center_x = ox + adjw / 2;
-                                let center_y = oy + adjh / 2;
-                                debug!("Falling back to first stored output center {}x{}", center_x, center_y);
-                                return Some((center_x, center_y));
-                            }
-                        }
-                    }
-                }
+impl Dispatch<zwlr_virtual_pointer_v1::ZwlrVirtualPointerV1, ()> for AppData {
+    fn event(
+        _: &mut Self,
+        _: &zwlr_virtual_pointer_v1::ZwlrVirtualPointerV1,
+        _: zwlr_virtual_pointer_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<AppData>,
+    ) {
+    }
+}
 
-                // 3) Fallback to environment variables (for older setups) or defaults
-                let width = std::env::var("WAYLAND_SCREEN_WIDTH")
-                    .ok()
-                    .and_then(|w| w.parse::<i32>().ok())
-                    .unwrap_or(1920);
-                let height = std::env::var("WAYLAND_SCREEN_HEIGHT")
-                    .ok()
-                    .and_then(|h| h.parse::<i32>().ok())
-                    .unwrap_or(1080);
-                let scale = std::env::var("WAYLAND_SCREEN_SCALE")
-                    .ok()
-                    .and_then(|s| s.parse::<i32>().ok())
-                    .unwrap_or(1);
-                let center_x = (width * scale) / 2;
-                let center_y = (height * scale) / 2;
-                return Some((center_x, center_y));
-            }
-        }
-        None
-    }
-
-    // Parse GNOME monitors.xml into a vector of monitors: (x, y, width, height, scale)
-    fn parse_gnome_monitors_list() -> Option<Vec<(i32, i32, i32, i32, i32)>> {
+impl Dispatch<zwp_relative_pointer_manager_v1::ZwpRelativePointerManagerV1, ()> for AppData {
+    fn event(
+        _: &mut Self,
+        _: &zwp_relative_pointer_manager_v1::ZwpRelativePointerManagerV1,
+        _: zwp_relative_pointer_manager_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<AppData>,
+    ) {
+    }
+}
+
+impl Dispatch<zwp_relative_pointer_v1::ZwpRelativePointerV1, ()> for AppData {
+    fn event(
+        state: &mut Self,
+        _: &zwp_relative_pointer_v1::ZwpRelativePointerV1,
+        event: zwp_relative_pointer_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<AppData>,
+    ) {
+        if let zwp_relative_pointer_v1::Event::RelativeMotion {
+            dx_unaccel, dy_unaccel, ..
+        } = event
+        {
+            // Only while locked: otherwise XWayland already sees the real motion
+            if !state.locked_acquired || state.locked_pointer.is_none() {
+                return;
+            }
+            if let Some(forwarder) = &mut state.motion_forwarder {
+                forwarder.forward(dx_unaccel, dy_unaccel);
+            }
+        }
+    }
+}
+
+impl Dispatch<zwp_confined_pointer_v1::ZwpConfinedPointerV1, ()> for AppData {
+    fn event(
+        state: &mut Self,
+        _: &zwp_confined_pointer_v1::ZwpConfinedPointerV1,
+        event: zwp_confined_pointer_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<AppData>,
+    ) {
+        match event {
+            zwp_confined_pointer_v1::Event::Confined => {
+                info!("🔒 Pointer successfully confined!");
+                state.locked_acquired = true;
+            }
+            zwp_confined_pointer_v1::Event::Unconfined => {
+                // Persistent like the lock, so it may be confined again
+                info!("🔓 Pointer unconfined by the compositor");
+                state.locked_acquired = false;
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<wl_region::WlRegion, ()> for AppData {
+    fn event(
+        _: &mut Self,
+        _: &wl_region::WlRegion,
+        _: wl_region::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<AppData>,
+    ) {
+    }
+}
+
+// Anything but a full socket buffer means the compositor connection is gone
+fn is_connection_error(e: &WaylandError) -> bool {
+    !matches!(e, WaylandError::Io(io) if io.kind() == std::io::ErrorKind::WouldBlock)
+}
+
+// Roundtrip that gives up after WAYLAND_CONNECT_TIMEOUT, so a compositor
+// that accepts the connection but never answers can't hang the daemon
+fn roundtrip_with_timeout(
+    conn: &Connection,
+    event_queue: &mut wayland_client::EventQueue<AppData>,
+    app_data: &mut AppData,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use std::os::unix::io::AsRawFd;
+    let done = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    conn.display().sync(&event_queue.handle(), done.clone());
+    let deadline = std::time::Instant::now() + WAYLAND_CONNECT_TIMEOUT;
+    loop {
+        event_queue.flush()?;
+        event_queue.dispatch_pending(app_data)?;
+        if done.load(std::sync::atomic::Ordering::Relaxed) {
+            return Ok(());
+        }
+        // None means events are already queued; dispatch them first
+        let Some(guard) = event_queue.prepare_read() else {
+            continue;
+        };
+        let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+        let mut pollfd = libc::pollfd {
+            fd: guard.connection_fd().as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ready = unsafe { libc::poll(&mut pollfd, 1, remaining.as_millis().min(i32::MAX as u128) as i32) };
+        if ready > 0 {
+            guard.read()?;
+        } else if ready == 0 {
+            return Err("timed out waiting for the compositor".into());
+        } else {
+            let err = std::io::Error::last_os_error();
+            if err.kind() != std::io::ErrorKind::Interrupted {
+                return Err(err.into());
+            }
+        }
+    }
+}
+
+// Region covering `rect` (root coordinates) on a lock surface whose top-left
+// corner sits at `origin`; None confines to the whole surface
+fn confine_region(
+    compositor: &wl_compositor::WlCompositor,
+    rect: Option<(i32, i32, i32, i32)>,
+    origin: (i32, i32),
+    qh: &QueueHandle<AppData>,
+) -> Option<wl_region::WlRegion> {
+    let (x, y, width, height) = rect?;
+    let region = compositor.create_region(qh, ());
+    region.add(x - origin.0, y - origin.1, width, height);
+    Some(region)
+}
+
+struct PointerLockDaemon {
+    connection: Option<Connection>,
+    app_data: Option<AppData>,
+    event_queue: Option<wayland_client::EventQueue<AppData>>,
+    wayland_source: Option<glib::SourceId>, // Main loop watch on the Wayland socket
+    wayland_lost: bool, // A Wayland request or dispatch failed; reset on the next update
+    wayland_reconnecting: bool, // The reconnect timer is armed while there is no connection
+    x11_watcher: Option<X11Watcher>, // Event-driven X11 state (cursor visibility, active window)
+    x11_source: Option<glib::SourceId>, // Main loop watch on the X11 connection fd
+    window_info_cache: WindowInfoCache, // Identities of windows on the current X connection
+    xwayland: XWaylandLocator, // Finds XWayland when DISPLAY wasn't set at startup
+    is_locked: bool, // Track current lock state
+    locked_window: Option<x11::xlib::Window>, // Client window the lock was requested for
+    lock_mode: ConstraintMode, // Lock or confine, from the detection that requested it
+    lock_rect: Option<(i32, i32, i32, i32)>, // Window rectangle the confine region follows
+    lock_output: Option<(wl_output::WlOutput, (i32, i32, i32, i32))>, // Output the lock surface is on and its root rectangle
+    // Candidate lock decision and when it was first seen, while debouncing
+    pending_lock: Option<(bool, std::time::Instant)>,
+    config: Config, // Current configuration (delays, intervals, screen fallback)
+    recheck_scheduled: bool, // A one-shot update is queued for the pending decision
+    poll_source: Option<glib::SourceId>, // Safety-net poll timer, re-armed when its interval changes
+    warp_thread: Option<std::thread::JoinHandle<()>>, // Thread for warping cursor
+    warp_stop: Option<std::sync::Arc<std::sync::atomic::AtomicBool>>, // Signal to stop warping
+}
+
+impl PointerLockDaemon {
+    fn new(config: Config) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut xwayland = XWaylandLocator::new();
+        let x11_watcher = Self::open_x11(&mut xwayland);
+        debug!("Using config: {:?}", config);
+
+        // Try to connect to Wayland; the reconnect timer keeps trying if it isn't up
+        let (connection, app_data, event_queue) = match Self::connect_wayland() {
+            Ok((connection, app_data, event_queue)) => (Some(connection), Some(app_data), Some(event_queue)),
+            Err(e) => {
+                warn!("Failed to connect to Wayland: {}, will keep retrying", e);
+                (None, None, None)
+            }
+        };
+
+        Ok(PointerLockDaemon {
+            connection,
+            app_data,
+            event_queue,
+            wayland_source: None,
+            wayland_lost: false,
+            x11_watcher,
+            x11_source: None,
+            window_info_cache: WindowInfoCache::default(),
+            xwayland,
+            is_locked: false,
+            locked_window: None,
+            lock_mode: ConstraintMode::default(),
+            lock_rect: None,
+            lock_output: None,
+            pending_lock: None,
+            config: config.clone(),
+            recheck_scheduled: false,
+            poll_source: None,
+            wayland_reconnecting: false,
+            warp_thread: None,
+            warp_stop: None,
+        })
+    }
+
+    // Connect to the compositor and bind every global the daemon uses
+    fn connect_wayland() -> Result<WaylandSession, Box<dyn std::error::Error>> {
+        let conn = Connection::connect_to_env()?;
+        info!("Connected to Wayland display");
+
+        let mut app_data = AppData {
+            pointer_constraints: None,
+            seat: None,
+            pointer: None,
+            keyboard: None,
+            compositor: None,
+            wm_base: None,
+            layer_shell: None,
+            shm: None,
+            viewporter: None,
+            xdg_output_manager: None,
+            relative_pointer_manager: None,
+            pointer_warp: None,
+            virtual_pointer_manager: None,
+            pointer_enter_serial: None,
+            relative_pointer: None,
+            motion_forwarder: None,
+            lock_origin: (0, 0),
+            lock_surface: None,
+            lock_buffer: None,
+            game_cursor: None,
+            locked_pointer: None,
+            confined_pointer: None,
+            locked_acquired: false,
+            outputs: Vec::new(),
+            globals: std::collections::HashMap::new(),
+            outputs_changed: false,
+            constraints_lost: false,
+        };
+
+        let display = conn.display();
+        let mut event_queue = conn.new_event_queue();
+        let qh = event_queue.handle();
+
+        // Get the registry and bind to global objects
+        let _registry = display.get_registry(&qh, ());
+
+        // First roundtrip to get all globals
+        roundtrip_with_timeout(&conn, &mut event_queue, &mut app_data)?;
+
+        // Second roundtrip to get seat capabilities after binding
+        if app_data.seat.is_some() {
+            debug!("Doing second roundtrip to get seat capabilities...");
+            roundtrip_with_timeout(&conn, &mut event_queue, &mut app_data)?;
+        }
+
+        // The lock surface itself is created when locking; prepare its buffer
+        if app_data.compositor.is_none() {
+            warn!("Warning: Missing compositor, cannot create surface");
+        }
+        if app_data.layer_shell.is_some() {
+            debug!("Using layer-shell overlay as pointer lock target");
+        } else if app_data.wm_base.is_some() {
+            info!("zwlr_layer_shell_v1 not available, using a fullscreen xdg_toplevel as pointer lock target");
+        } else {
+            warn!("Neither zwlr_layer_shell_v1 nor xdg_wm_base available, pointer lock may not activate");
+        }
+        if app_data.layer_shell.is_some() || app_data.wm_base.is_some() {
+            match &app_data.shm {
+                Some(shm) => app_data.lock_buffer = lock_surface::transparent_buffer(shm, &qh),
+                None => warn!("wl_shm not available, the lock surface cannot be mapped and pointer lock may not activate"),
+            }
+        }
+
+        info!("Wayland protocols initialized successfully");
+        Ok((conn, app_data, event_queue))
+    }
+
+    // Connect to XWayland if it is running
+    fn open_x11(xwayland: &mut XWaylandLocator) -> Option<X11Watcher> {
+        let display = xwayland.locate()?;
+        match X11Watcher::open(&display) {
+            Some(watcher) => {
+                info!("Connected to X display {:?}", display);
+                Some(watcher)
+            }
+            None => {
+                // The discovery is kept and retried until the sockets change
+                debug!("Could not open X display {:?}", display);
+                None
+            }
+        }
+    }
+
+    fn should_lock(&mut self) -> Detection {
+        should_lock_pointer(self.x11_watcher.as_ref(), &self.config, &mut self.window_info_cache)
+    }
+
+    // Process events already queued on the X11 connection; returns true if
+    // watched state changed
+    fn drain_x11_events(&mut self) -> bool {
+        match &mut self.x11_watcher {
+            Some(watcher) => watcher.process_events(),
+            None => false,
+        }
+    }
+
+    fn x11_fd(&self) -> Option<std::os::unix::io::RawFd> {
+        self.x11_watcher.as_ref().map(|w| w.fd())
+    }
+
+    // Handle readable X11 connection; re-evaluate the lock only if watched state changed
+    fn process_x11_events(&mut self) {
+        if self.drain_x11_events() {
+            self.update();
+        } else {
+            self.sync_game_cursor();
+        }
+    }
+
+    // Mirror the game's X cursor onto the lock surface whenever XFixes
+    // reports a new one, so it stays visible while our surface holds
+    // pointer focus (confinement keeps the cursor moving over it)
+    fn sync_game_cursor(&mut self) {
+        let (Some(app_data), Some(event_queue), Some(watcher)) =
+            (&mut self.app_data, &self.event_queue, &self.x11_watcher)
+        else {
+            return;
+        };
+        let (Some(_), Some(compositor), Some(shm)) = (&app_data.lock_surface, &app_data.compositor, &app_data.shm) else {
+            return;
+        };
+        if app_data.game_cursor.as_ref().is_some_and(|c| c.serial() == watcher.cursor_serial()) {
+            return;
+        }
+        let Some(image) = watcher.cursor_image() else {
+            return;
+        };
+        debug!("Showing X cursor serial={} {}x{} on the lock surface", image.serial, image.width, image.height);
+        let qh = event_queue.handle();
+        let game_cursor = app_data.game_cursor.get_or_insert_with(|| GameCursor::new(compositor, &qh));
+        game_cursor.set_image(shm, &image, &qh);
+        // Already on our surface: the new image needs setting as the cursor again
+        if let (Some(pointer), Some(serial)) = (&app_data.pointer, app_data.pointer_enter_serial) {
+            game_cursor.apply(pointer, serial);
+        }
+        if let Err(e) = event_queue.flush() {
+            debug!("❌ Error flushing cursor image: {}", e);
+            self.wayland_lost |= is_connection_error(&e);
+        }
+    }
+
+    // XWayland went away: release the lock, stop warping and drop the
+    // connection so the reconnect timer can open a new one
+    fn handle_x11_lost(&mut self) {
+        warn!("X11 connection lost, unlocking until XWayland is back");
+        self.pending_lock = None;
+        self.unlock_pointer();
+        self.stop_warp_thread();
+        self.locked_window = None;
+        if let Some(source) = self.x11_source.take() {
+            source.remove();
+        }
+        self.x11_watcher = None;
+        self.window_info_cache.clear();
+        self.xwayland.detach();
+    }
+
+    fn get_wayland_surface_center(&self) -> Option<(i32, i32)> {
+        let app_data = self.app_data.as_ref()?;
+        app_data.compositor.as_ref()?;
+        let center = |(x, y, w, h): (i32, i32, i32, i32)| (x + w / 2, y + h / 2);
+        let focused = self.get_focused_x11_window_center();
+        if focused.is_none() {
+            debug!("No focused X11 window center available to choose monitor");
+        }
+        let contains = |(x, y, w, h): (i32, i32, i32, i32)| {
+            focused.is_some_and(|(fx, fy)| fx >= x && fx < x + w && fy >= y && fy < y + h)
+        };
+
+        // 1) Output rectangles from xdg-output, else wl_output; the same
+        // coordinate space the lock surface origin comes from, so the lock
+        // output itself is used while there is one
+        if let Some((_, rect)) = &self.lock_output {
+            return Some(center(*rect));
+        }
+        let outputs: Vec<_> = app_data.outputs.iter().filter_map(Output::rect).collect();
+        if let Some(rect) = outputs.iter().copied().find(|rect| contains(*rect)).or(outputs.first().copied()) {
+            debug!("Selected output {:?} for the pointer centre", rect);
+            return Some(center(rect));
+        }
+
+        // 2) GNOME monitors.xml, for when no output reported its geometry
+        if let Some(monitors) = Self::parse_gnome_monitors_list() {
+            debug!("parse_gnome_monitors_list returned {} monitors: {:?}", monitors.len(), monitors);
+            let rect = monitors.iter().copied().find(|rect| contains(*rect)).unwrap_or(monitors[0]);
+            debug!("Selected monitor {:?} from monitors.xml", rect);
+            return Some(center(rect));
+        }
+
+        // 3) Fallback to configured screen size (env variables override it for older setups)
+        let config::ScreenFallback { width, height, scale } = self.config.screen;
+        Some(((width * scale) / 2, (height * scale) / 2))
+    }
+
+    // Parse GNOME monitors.xml into logical monitor rectangles
+    fn parse_gnome_monitors_list() -> Option<Vec<(i32, i32, i32, i32)>> {
         use std::fs;
         let home = std::env::var("HOME").ok()?;
         let path = format!("{}/.config/monitors.xml", home);
         let contents = fs::read_to_string(path).ok()?;
-        let lower = contents.to_lowercase();
-
-        let mut monitors = Vec::new();
-
-        // Very simple parse: find <monitor> or <logicalmonitor> entries and extract position/size/scale
-        let mut idx = 0usize;
-        while let Some(start) = lower[idx..].find('<') {
-            idx += start;
-            if lower[idx..].starts_with("<monitor") || lower[idx..].starts_with("<logicalmonitor") {
-                let end_tag = if lower[idx..].starts_with("<monitor") { "</monitor>" } else { "</logicalmonitor>" };
-                if let Some(end_rel) = lower[idx..].find(end_tag) {
-                    let snippet = &lower[idx..idx + end_rel];
-                    // extract tags
-                    let extract = |tag: &str| -> Option<i32> {
-                        let open = format!("<{}>", tag);
-                        let close = format!("</{}>", tag);
-                        let a = snippet.find(&open)? + open.len();
-                        let b = snippet[a..].find(&close)? + a;
-                        snippet[a..b].trim().parse::<i32>().ok()
-                    };
-                    let x = extract("x").unwrap_or(0);
-                    let y = extract("y").unwrap_or(0);
-                    let width = extract("width").or_else(|| extract("modewidth")).unwrap_or(1920);
-                    let height = extract("height").or_else(|| extract("modeheight")).unwrap_or(1080);
-                    let scale = extract("scale").unwrap_or(1);
-                    monitors.push((x, y, width, height, scale));
-                    idx += end_rel;
-                    continue;
-                }
-            }
-            idx += 1;
-        }
-
+        let monitors = parse_monitors_xml(&contents);
         if monitors.is_empty() { None } else { Some(monitors) }
     }
 
     // Get center of the currently focused X11 window (root coordinates)
-    fn get_focused_x11_window_center() -> Option<(i32, i32)> {
-        unsafe {
-            let display = x11::xlib::XOpenDisplay(ptr::null());
-            if display.is_null() {
-                debug!("XOpenDisplay returned null");
-                return None;
-            }
-            debug!("XOpenDisplay succeeded");
-            let mut focus: x11::xlib::Window = 0;
-            let mut revert: i32 = 0;
-            x11::xlib::XGetInputFocus(display, &mut focus, &mut revert);
-            debug!("XGetInputFocus -> focus={} revert={}", focus, revert);
-            if focus == 0 {
-                debug!("No focused window (focus == 0)");
-                x11::xlib::XCloseDisplay(display);
-                return None;
-            }
-            let mut attrs: x11::xlib::XWindowAttributes = std::mem::zeroed();
-            let got_attrs = x11::xlib::XGetWindowAttributes(display, focus, &mut attrs);
-            debug!("XGetWindowAttributes returned {} for window {}", got_attrs, focus);
-            if got_attrs == 0 {
-                debug!("Failed to get window attributes for {}", focus);
-                x11::xlib::XCloseDisplay(display);
-                return None;
-            }
-            // Translate window coordinates to root
-            let mut root_x = 0i32;
-            let mut root_y = 0i32;
-            let mut child_return: x11::xlib::Window = 0;
-            let tx = x11::xlib::XTranslateCoordinates(
-                display,
-                focus,
-                x11::xlib::XDefaultRootWindow(display),
-                0,
-                0,
-                &mut root_x,
-                &mut root_y,
-                &mut child_return,
-            );
-            debug!("XTranslateCoordinates returned {} root_x={} root_y={} child_return={}", tx, root_x, root_y, child_return);
-            let center_x = root_x + attrs.width / 2;
-            let center_y = root_y + attrs.height / 2;
-            debug!("Focused window {} center at {}x{} (attrs w={} h={})", focus, center_x, center_y, attrs.width, attrs.height);
-            x11::xlib::XCloseDisplay(display);
-            Some((center_x as i32, center_y as i32))
-        }
+    fn get_focused_x11_window_center(&self) -> Option<(i32, i32)> {
+        let Some(watcher) = &self.x11_watcher else {
+            debug!("No X11 connection");
+            return None;
+        };
+        let Some(focus) = watcher.focused_window() else {
+            debug!("No focused window");
+            return None;
+        };
+        let display = watcher.display();
+        let focus = x11_watcher::resolve_client_window(display, watcher.atoms(), focus);
+        let Some((x, y, width, height)) = window_rect(display, watcher.root(), focus) else {
+            debug!("Failed to get the geometry of window {}", focus);
+            return None;
+        };
+        let center = (x + width / 2, y + height / 2);
+        debug!("Focused window {} center at {:?} (size {}x{})", focus, center, width, height);
+        Some(center)
+    }
+
+    // wl_output containing the focused game window and its rectangle, so the
+    // lock surface is mapped on the game's monitor
+    fn game_output(&self) -> Option<(wl_output::WlOutput, (i32, i32, i32, i32))> {
+        let app_data = self.app_data.as_ref()?;
+        let (fx, fy) = self.get_focused_x11_window_center()?;
+        app_data.outputs.iter().find_map(|output| {
+            let (ox, oy, rw, rh) = output.rect()?;
+            let contains = fx >= ox && fx < ox + rw && fy >= oy && fy < oy + rh;
+            contains.then(|| (output.output.clone(), (ox, oy, rw, rh)))
+        })
+    }
+
+    // Root position of the lock surface's output
+    fn lock_origin(&self) -> (i32, i32) {
+        self.lock_output
+            .as_ref()
+            .map_or((0, 0), |(_, (x, y, _, _))| (*x, *y))
+    }
+
+    // A lock is held, or requested and waiting for the compositor to
+    // activate it; the lock surface only exists in between
+    fn lock_requested(&self) -> bool {
+        self.is_locked
+            || self.app_data.as_ref().is_some_and(|app_data| {
+                app_data.locked_pointer.is_some()
+                    || app_data.confined_pointer.is_some()
+                    || app_data.lock_surface.is_some()
+            })
     }
 
     fn lock_pointer(&mut self) {
-        // Prevent multiple lock attempts
-        if self.is_locked {
+        // Prevent multiple lock attempts; a pending one stays until the
+        // compositor activates it or the window no longer qualifies
+        if self.lock_requested() {
             return;
         }
+        // Input reaching our surface is injected into XWayland; without that
+        // connection the game would lose the pointer, so stay unlocked
+        if let Some(app_data) = &mut self.app_data {
+            if app_data.motion_forwarder.is_none() {
+                let x11_display = self.x11_watcher.as_ref().map(|w| w.display_name().to_owned());
+                app_data.motion_forwarder = x11_display
+                    .as_deref()
+                    .zip(self.locked_window)
+                    .and_then(|(name, window)| MotionForwarder::open(name, window));
+            }
+            if app_data.motion_forwarder.is_none() {
+                warn!("Cannot forward input to XWayland, not locking the pointer");
+                self.locked_window = None;
+                self.lock_rect = None;
+                return;
+            }
+        }
+        let mode = self.lock_mode;
+        self.lock_output = self.game_output();
+        let output = self.lock_output.as_ref().map(|(output, _)| output.clone());
+        if mode == ConstraintMode::Lock {
+            if let Some(center) = self.get_wayland_surface_center() {
+                self.center_virtual_pointer(center);
+            }
+        }
+        let origin = self.lock_origin();
+        let lock_rect = self.lock_rect;
         if let (Some(app_data), Some(event_queue)) = (&mut self.app_data, &mut self.event_queue) {
-            if let (Some(pointer_constraints), Some(pointer), Some(surface)) = (
+            // Map the lock target on the game's output. It is mapped on its
+            // first configure and the compositor activates the constraint
+            // once it has pointer focus; Locked/Confined then arrive through
+            // the Wayland fd source, so nothing is waited for here.
+            if app_data.pointer_constraints.is_some() && app_data.pointer.is_some() {
+                app_data.lock_origin = origin;
+                app_data.lock_surface = LockSurface::create(app_data, output.as_ref(), &event_queue.handle());
+            }
+            if let (Some(pointer_constraints), Some(pointer), Some(lock_surface)) = (
                 &app_data.pointer_constraints,
                 &app_data.pointer,
-                &app_data.surface,
+                &app_data.lock_surface,
             ) {
-                // Check if we already have a locked pointer object
-                if app_data.locked_pointer.is_some() {
-                    return;
-                }
-                info!("🔒 Locking pointer for XWayland fullscreen application with hidden cursor");
-                // Lock the pointer to our surface
-                let locked_pointer = pointer_constraints.lock_pointer(
-                    surface,
-                    pointer,
-                    None, // No region restriction
-                    zwp_pointer_constraints_v1::Lifetime::Persistent,
-                    &event_queue.handle(),
-                    (),
-                );
-                // Store the locked pointer (we'll wait for the compositor to ACK with Locked event)
-                app_data.locked_pointer = Some(locked_pointer);
-                // Process events to handle the lock response which should set locked_acquired
-                match event_queue.dispatch_pending(app_data) {
-                    Ok(_) => {
-                        info!("✅ Pointer lock request sent, checking compositor acknowledgement...");
-                        // If compositor acknowledged the lock (Locked event handler sets this), start warping
-                        if app_data.locked_acquired {
-                            info!("✅ Compositor acknowledged pointer lock");
-                            self.is_locked = true;
-                            // Start cursor warping thread
-                            let stop_flag = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
-                            let stop_flag_clone = stop_flag.clone();
-                            let center = self.get_wayland_surface_center().unwrap_or((960, 540));
-                            self.warp_stop = Some(stop_flag.clone());
-                            self.warp_thread = Some(std::thread::spawn(move || {
-                                use std::time::Duration;
-                                unsafe {
-                                    let display = x11::xlib::XOpenDisplay(std::ptr::null());
-                                    if display.is_null() {
-                                        error!("Could not open X display for warping");
-                                        return;
-                                    }
-                                    let screen = x11::xlib::XDefaultScreen(display);
-                                    let root = x11::xlib::XRootWindow(display, screen);
-                                    let center_x = center.0;
-                                    let center_y = center.1;
-                                    #[cfg(debug_assertions)]
-                                    {
-                                        debug!("Starting cursor warping thread to ({}, {})", center_x, center_y);
-                                    }
-                                    while !stop_flag_clone.load(std::sync::atomic::Ordering::Relaxed) {
-                                        x11::xlib::XWarpPointer(
-                                            display, 0, root, 0, 0, 0, 0, center_x, center_y,
-                                        );
-                                        x11::xlib::XFlush(display);
-                                        std::thread::sleep(Duration::from_millis(250));
-                                    }
-                                    x11::xlib::XCloseDisplay(display);
-                                }
-                            }));
-                        } else {
-                            // Compositor did not acknowledge the lock yet; undo and log
-                            warn!("Compositor did not acknowledge pointer lock (no Locked event received)");
-                            app_data.locked_pointer = None;
-                            app_data.locked_acquired = false;
+                match mode {
+                    ConstraintMode::Lock => {
+                        info!("🔒 Locking pointer for XWayland fullscreen application with hidden cursor");
+                        // Lock the pointer to our surface
+                        let locked_pointer = pointer_constraints.lock_pointer(
+                            lock_surface.surface(),
+                            pointer,
+                            None, // No region restriction
+                            zwp_pointer_constraints_v1::Lifetime::Persistent,
+                            &event_queue.handle(),
+                            (),
+                        );
+                        // Store the locked pointer (the compositor ACKs it with a Locked event)
+                        app_data.locked_pointer = Some(locked_pointer);
+                        // The game sees no motion while the pointer is locked on our
+                        // surface; forward the relative deltas to XWayland instead
+                        if let Some(manager) = &app_data.relative_pointer_manager {
+                            debug!("Forwarding relative motion through XTest");
+                            app_data.relative_pointer =
+                                Some(manager.get_relative_pointer(pointer, &event_queue.handle(), ()));
                         }
                     }
-                    Err(_e) => {
-                        error!("❌ Error processing pointer lock events: {}", _e);
-                        self.is_locked = false; // Reset on error
-                        self.warp_stop = None;
-                        self.warp_thread = None;
+                    ConstraintMode::Confine => {
+                        info!("🔒 Confining pointer to window rectangle {:?}", lock_rect);
+                        let region = app_data
+                            .compositor
+                            .as_ref()
+                            .and_then(|compositor| confine_region(compositor, lock_rect, origin, &event_queue.handle()));
+                        let confined_pointer = pointer_constraints.confine_pointer(
+                            lock_surface.surface(),
+                            pointer,
+                            region.as_ref(),
+                            zwp_pointer_constraints_v1::Lifetime::Persistent,
+                            &event_queue.handle(),
+                            (),
+                        );
+                        // The region is copied by the request
+                        if let Some(region) = region {
+                            region.destroy();
+                        }
+                        app_data.confined_pointer = Some(confined_pointer);
                     }
                 }
+                info!("✅ Pointer lock requested, waiting for the compositor to activate it");
+                if let Err(e) = event_queue.flush() {
+                    error!("❌ Error sending pointer lock requests: {}", e);
+                    self.wayland_lost |= is_connection_error(&e);
+                }
             } else {
+                app_data.stop_relative_motion();
                 #[cfg(debug_assertions)]
                 {
                     if app_data.pointer_constraints.is_none() {
@@ -751,7 +1659,7 @@ impl PointerLockDaemon {
                     if app_data.pointer.is_none() {
                         debug!("❌ Pointer device not available");
                     }
-                    if app_data.surface.is_none() {
+                    if app_data.lock_surface.is_none() {
                         debug!("❌ Surface not available");
                     }
                     if app_data.compositor.is_none() {
@@ -762,26 +1670,34 @@ impl PointerLockDaemon {
         }
     }
 
+    // Release the lock, or withdraw a pending request, and unmap the surface
     fn unlock_pointer(&mut self) {
-        // Only unlock if we're currently locked
-        if !self.is_locked {
-            return;
-        }
+        self.stop_warp_thread();
+        self.is_locked = false;
+        self.locked_window = None;
+        self.lock_rect = None;
         if let Some(app_data) = &mut self.app_data {
-            if let Some(locked_pointer) = app_data.locked_pointer.take() {
+            let locked_pointer = app_data.locked_pointer.take();
+            let confined_pointer = app_data.confined_pointer.take();
+            // The constraint may already be gone with its seat; the surface still needs tearing down
+            if locked_pointer.is_some() || confined_pointer.is_some() || app_data.lock_surface.is_some() {
                 info!("🔓 Unlocking pointer...");
-                locked_pointer.destroy();
-                self.is_locked = false;
-                // Stop cursor warping thread
-                if let Some(stop_flag) = &self.warp_stop {
-                    stop_flag.store(true, std::sync::atomic::Ordering::Relaxed);
+                if let Some(locked_pointer) = locked_pointer {
+                    locked_pointer.destroy();
                 }
-                if let Some(handle) = self.warp_thread.take() {
-                    let _ = handle.join();
+                if let Some(confined_pointer) = confined_pointer {
+                    confined_pointer.destroy();
                 }
-                self.warp_stop = None;
+                app_data.stop_relative_motion();
+                app_data.locked_acquired = false;
+                app_data.destroy_lock_surface();
                 // Process events to handle the unlock (non-blocking)
                 if let Some(event_queue) = &mut self.event_queue {
+                    // Send the destroy requests now rather than with the next roundtrip
+                    if let Err(e) = event_queue.flush() {
+                        debug!("❌ Error flushing pointer unlock requests: {}", e);
+                        self.wayland_lost |= is_connection_error(&e);
+                    }
                     match event_queue.dispatch_pending(app_data) {
                         Ok(_) => {
                             info!("✅ Pointer unlock processed");
@@ -789,6 +1705,7 @@ impl PointerLockDaemon {
                         Err(_e) => {
                             #[cfg(debug_assertions)]
                             debug!("❌ Error processing pointer unlock events: {}", _e);
+                            self.wayland_lost = true;
                         }
                     }
                 }
@@ -796,66 +1713,623 @@ impl PointerLockDaemon {
         }
     }
 
+    // Stop the cursor warping thread, if running, and wait for it to exit
+    fn stop_warp_thread(&mut self) {
+        if let Some(stop_flag) = self.warp_stop.take() {
+            stop_flag.store(true, std::sync::atomic::Ordering::Relaxed);
+        }
+        if let Some(handle) = self.warp_thread.take() {
+            let _ = handle.join();
+        }
+    }
+
     fn update(&mut self) {
-        let should_lock = self.should_lock();
+        self.drain_x11_events();
+        let mut detection = self.should_lock();
+        // Detection shares the watcher's connection, so events that arrived
+        // while it waited for replies sit in Xlib's queue and won't wake the
+        // fd source; apply them now and re-detect if they changed anything
+        if self.drain_x11_events() {
+            detection = self.should_lock();
+        }
+        if self.x11_watcher.as_ref().is_some_and(|w| w.is_connection_lost()) {
+            self.handle_x11_lost();
+            return;
+        }
+        self.dispatch_wayland();
+        if self.wayland_lost {
+            self.handle_wayland_lost();
+        }
+        self.handle_hotplug();
 
-        if should_lock && !self.is_locked {
-            self.lock_pointer();
-        } else if !should_lock && self.is_locked {
+        // Our fullscreen toplevel taking focus leaves X without an active
+        // window; the game still owns the lock, so keep it
+        if detection.window.is_none()
+            && self.lock_requested()
+            && self.app_data.as_ref().is_some_and(|app_data| app_data.lock_surface_took_focus())
+        {
+            detection = Detection {
+                should_lock: true,
+                window: self.locked_window,
+                rule: None,
+                mode: self.lock_mode,
+                rect: self.lock_rect,
+            };
+        }
+
+        // Confinement needs the lock surface to cover the output, which a
+        // 1x1 buffer only does with wp_viewporter; lock instead without it
+        let confine_unavailable = detection.mode == ConstraintMode::Confine
+            && self.app_data.as_ref().is_some_and(|app_data| app_data.viewporter.is_none());
+        if confine_unavailable {
+            detection.mode = ConstraintMode::Lock;
+        }
+
+        // A rule switched between lock and confine: release and let the new mode lock again
+        if self.lock_requested() && detection.should_lock && detection.mode != self.lock_mode {
+            info!("Constraint mode changed to {:?}, re-locking", detection.mode);
+            self.pending_lock = None;
             self.unlock_pointer();
         }
+
+        // Keep the confine region on the window as it moves or resizes
+        if self.lock_requested()
+            && self.lock_mode == ConstraintMode::Confine
+            && detection.window == self.locked_window
+            && detection.rect.is_some()
+            && detection.rect != self.lock_rect
+        {
+            self.update_confine_region(detection.rect);
+        }
+
+        let current = LockState { requested: self.lock_requested(), window: self.locked_window };
+        let delays = (self.config.lock_delay, self.config.unlock_delay);
+        let now = std::time::Instant::now();
+        let (action, pending) = decide_lock(&detection, current, self.pending_lock, now, delays);
+        if pending.is_some() && pending.map(|(lock, _)| lock) != self.pending_lock.map(|(lock, _)| lock) {
+            debug!("Lock decision changed to {}, debouncing", detection.should_lock);
+        }
+        self.pending_lock = pending;
+        if matches!(action, LockAction::Lock | LockAction::Unlock) {
+            if let Some(rule) = &detection.rule {
+                info!("Rule '{}' decided should_lock={}", rule, detection.should_lock);
+            }
+        }
+        match action {
+            LockAction::Keep => {}
+            LockAction::UnlockNow => {
+                info!("Focus left locked window, unlocking immediately");
+                self.unlock_pointer();
+            }
+            LockAction::Unlock => self.unlock_pointer(),
+            LockAction::Lock => {
+                // Focus moved straight to another window that locks
+                if self.lock_requested() {
+                    info!("Focus left locked window, unlocking immediately");
+                    self.unlock_pointer();
+                }
+                if confine_unavailable {
+                    warn!("wp_viewporter not available, locking the pointer instead of confining it");
+                }
+                self.locked_window = detection.window;
+                self.lock_mode = detection.mode;
+                self.lock_rect = detection.rect;
+                self.lock_pointer();
+            }
+        }
         // If the compositor acknowledged the locked pointer (Locked event) but we
         // haven't yet started the warp thread / marked is_locked, do so now.
         self.start_warp_if_needed();
+        // Locking or unlocking may have hit a dead connection
+        if self.wayland_lost {
+            self.handle_wayland_lost();
+        }
+        // Locking looks up the game's output and warp target with more X
+        // round trips; events read meanwhile sit in Xlib's queue too
+        if self.drain_x11_events() {
+            self.update();
+            return;
+        }
+        self.sync_game_cursor();
+        if self.wayland_lost {
+            self.handle_wayland_lost();
+        }
     }
 
-    // Start the warp thread when compositor ACK'ed the lock (locked_acquired == true)
-    fn start_warp_if_needed(&mut self) {
-        if self.is_locked {
+    // Read and dispatch whatever the compositor sent without blocking; a
+    // connection error means the compositor is gone
+    fn dispatch_wayland(&mut self) {
+        let (Some(app_data), Some(event_queue)) = (&mut self.app_data, &mut self.event_queue) else {
             return;
+        };
+        let read = event_queue.flush().and_then(|_| match event_queue.prepare_read() {
+            Some(guard) => guard.read(),
+            None => Ok(0),
+        });
+        if let Err(e) = read {
+            if is_connection_error(&e) {
+                warn!("Wayland connection error: {}", e);
+                self.wayland_lost = true;
+                return;
+            }
+        }
+        if let Err(e) = event_queue.dispatch_pending(app_data) {
+            warn!("Error dispatching Wayland events: {}", e);
+            self.wayland_lost = true;
+            return;
+        }
+        // Send replies made while dispatching, such as xdg_wm_base pongs
+        if let Err(e) = event_queue.flush() {
+            if is_connection_error(&e) {
+                warn!("Wayland connection error: {}", e);
+                self.wayland_lost = true;
+            }
+        }
+    }
+
+    fn wayland_fd(&self) -> Option<std::os::unix::io::RawFd> {
+        use std::os::unix::io::AsRawFd;
+        self.connection.as_ref().map(|conn| conn.backend().poll_fd().as_raw_fd())
+    }
+
+    // Handle a readable Wayland socket: dispatch the events and act on
+    // lock acknowledgements and hotplug right away, without a detection pass
+    fn process_wayland_events(&mut self) {
+        self.dispatch_wayland();
+        if self.wayland_lost {
+            self.handle_wayland_lost();
+            return;
+        }
+        self.handle_hotplug();
+        self.start_warp_if_needed();
+        if self.wayland_lost {
+            self.handle_wayland_lost();
+        }
+    }
+
+    // The compositor went away (crash or restart): drop every Wayland
+    // object and the warp thread; the reconnect timer rebinds the globals
+    // and the lock surface is recreated on the next lock
+    fn handle_wayland_lost(&mut self) {
+        warn!("Wayland connection lost, reconnecting when the compositor is back");
+        self.wayland_lost = false;
+        self.stop_warp_thread();
+        self.is_locked = false;
+        self.locked_window = None;
+        self.lock_rect = None;
+        self.lock_output = None;
+        self.pending_lock = None;
+        if let Some(source) = self.wayland_source.take() {
+            source.remove();
+        }
+        self.app_data = None;
+        self.event_queue = None;
+        self.connection = None;
+    }
+
+    // Try to connect to the compositor again; returns true once connected
+    fn reconnect_wayland(&mut self) -> bool {
+        match Self::connect_wayland() {
+            Ok((connection, app_data, event_queue)) => {
+                info!("Reconnected to Wayland display");
+                self.connection = Some(connection);
+                self.app_data = Some(app_data);
+                self.event_queue = Some(event_queue);
+                true
+            }
+            Err(e) => {
+                debug!("Wayland reconnect failed: {}", e);
+                false
+            }
         }
+    }
+
+    // React to seat and output hotplug seen on the Wayland connection: a
+    // lost constraint or lock output unlocks (the debounce locks again with
+    // the new devices), other output changes move the warp target
+    fn handle_hotplug(&mut self) {
+        let Some(app_data) = &mut self.app_data else {
+            return;
+        };
+        let constraints_lost = std::mem::take(&mut app_data.constraints_lost);
+        let outputs_changed = std::mem::take(&mut app_data.outputs_changed);
+        if !self.lock_requested() {
+            return;
+        }
+        let Some(app_data) = &self.app_data else {
+            return;
+        };
+        let lock_output = self.lock_output.as_ref().map(|(lock_output, _)| {
+            app_data
+                .outputs
+                .iter()
+                .find(|output| &output.output == lock_output)
+                .and_then(|output| Some((output.output.clone(), output.rect()?)))
+        });
+        if constraints_lost || matches!(lock_output, Some(None)) {
+            info!("Pointer constraint or its output went away, unlocking");
+            self.pending_lock = None;
+            self.unlock_pointer();
+            return;
+        }
+        if outputs_changed && self.is_locked && self.lock_mode == ConstraintMode::Lock {
+            debug!("Outputs changed, recomputing the warp target");
+            self.lock_output = lock_output.flatten();
+            self.stop_warp_thread();
+            self.start_recentring();
+        }
+    }
+
+    // Move the confine region to the window's new rectangle
+    fn update_confine_region(&mut self, rect: Option<(i32, i32, i32, i32)>) {
+        self.lock_rect = rect;
+        if let (Some(app_data), Some(event_queue)) = (&self.app_data, &self.event_queue) {
+            if let (Some(confined_pointer), Some(compositor), Some(lock_surface)) = (
+                &app_data.confined_pointer,
+                &app_data.compositor,
+                &app_data.lock_surface,
+            ) {
+                debug!("Updating confine region to {:?}", rect);
+                let region = confine_region(compositor, rect, self.lock_origin(), &event_queue.handle());
+                confined_pointer.set_region(region.as_ref());
+                if let Some(region) = region {
+                    region.destroy();
+                }
+                lock_surface.apply_constraint_state();
+                if let Err(e) = event_queue.flush() {
+                    debug!("❌ Error flushing confine region: {}", e);
+                }
+            }
+        }
+    }
+
+    // The poll is the only way to notice focus changes when the WM doesn't
+    // publish _NET_ACTIVE_WINDOW, so it runs at most FOCUS_POLL_INTERVAL apart then
+    fn poll_interval(&self) -> std::time::Duration {
+        let focus_untracked = self.x11_watcher.as_ref().is_some_and(|w| w.active_window().is_none());
+        if focus_untracked {
+            self.config.poll_interval.min(FOCUS_POLL_INTERVAL)
+        } else {
+            self.config.poll_interval
+        }
+    }
+
+    // Swap in a reloaded configuration
+    fn apply_config(&mut self, config: Config) {
+        info!("Applying configuration: {:?}", config);
+        let previous = std::mem::replace(&mut self.config, config);
+        // The warp thread sleeps for the interval it was started with
+        if previous.warp_interval != self.config.warp_interval && self.warp_thread.is_some() {
+            debug!("Warp interval changed, restarting re-centring");
+            self.stop_warp_thread();
+            self.start_recentring();
+        }
+    }
+
+    // Time left until the pending lock/unlock decision becomes stable
+    fn pending_remaining(&self) -> Option<std::time::Duration> {
+        self.pending_lock.map(|(lock, since)| {
+            let delay = if lock { self.config.lock_delay } else { self.config.unlock_delay };
+            delay.saturating_sub(since.elapsed())
+        })
+    }
+
+    // Follow the compositor's Locked/Unlocked (Confined/Unconfined) events:
+    // start the warp thread once the requested constraint is active, and
+    // pause it while the compositor has deactivated the constraint
+    fn start_warp_if_needed(&mut self) {
         if let Some(app_data) = &self.app_data {
-            if app_data.locked_acquired {
-                if app_data.locked_pointer.is_some() {
-                    info!("✅ Starting warp after compositor acknowledgement");
-                    // Start cursor warping thread
-                    let stop_flag = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
-                    let stop_flag_clone = stop_flag.clone();
-                    let center = self.get_wayland_surface_center().unwrap_or((960, 540));
-                    self.warp_stop = Some(stop_flag.clone());
-                    self.warp_thread = Some(std::thread::spawn(move || {
-                        use std::time::Duration;
-                        unsafe {
-                            let display = x11::xlib::XOpenDisplay(std::ptr::null());
-                            if display.is_null() {
-                                error!("Could not open X display for warping");
-                                return;
-                            }
-                            let screen = x11::xlib::XDefaultScreen(display);
-                            let root = x11::xlib::XRootWindow(display, screen);
-                            let center_x = center.0;
-                            let center_y = center.1;
-                            #[cfg(debug_assertions)]
-                            {
-                                debug!("Starting cursor warping thread to ({}, {})", center_x, center_y);
-                            }
-                            while !stop_flag_clone.load(std::sync::atomic::Ordering::Relaxed) {
-                                x11::xlib::XWarpPointer(
-                                    display, 0, root, 0, 0, 0, 0, center_x, center_y,
-                                );
-                                x11::xlib::XFlush(display);
-                                std::thread::sleep(Duration::from_millis(250));
-                            }
-                            x11::xlib::XCloseDisplay(display);
-                        }
-                    }));
-                    self.is_locked = true;
+            if self.is_locked {
+                if !app_data.constraint_active() {
+                    info!("Pointer constraint deactivated by the compositor, waiting for it to return");
+                    self.stop_warp_thread();
+                    self.is_locked = false;
                 }
+                return;
+            }
+            if app_data.locked_acquired && app_data.confined_pointer.is_some() {
+                info!("✅ Compositor acknowledged pointer confinement");
+                self.is_locked = true;
+                return;
+            }
+            if app_data.locked_acquired && app_data.locked_pointer.is_some() {
+                info!("✅ Starting warp after compositor acknowledgement");
+                self.start_recentring();
+                self.is_locked = true;
+            }
+        }
+    }
+
+    // Keep the cursor centred while locked. The cursor position hint and,
+    // where offered, a native warp through wp_pointer_warp_v1 only place the
+    // compositor's cursor; forwarded motion still moves the X cursor, so a
+    // thread keeps re-centring that with XWarpPointer.
+    fn start_recentring(&mut self) {
+        let center = self.get_wayland_surface_center().unwrap_or((960, 540));
+        if self.warp_native(center) {
+            debug!("Compositor cursor centred with wp_pointer_warp_v1, X cursor re-centred with XWarpPointer");
+        } else {
+            debug!("No wp_pointer_warp_v1 warp (unbound or pointer not on the lock surface), re-centring with XWarpPointer only");
+        }
+        let stop_flag = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let stop_flag_clone = stop_flag.clone();
+        let warp_interval = self.config.warp_interval;
+        let Some(x11_display) = self.x11_watcher.as_ref().map(|w| w.display_name().to_owned()) else {
+            return;
+        };
+        // Start cursor warping thread
+        self.warp_stop = Some(stop_flag.clone());
+        self.warp_thread = Some(std::thread::spawn(move || {
+            warp_pointer_loop(&x11_display, center, warp_interval, &stop_flag_clone);
+        }));
+    }
+
+    // Virtual pointer for re-centring on `center` (root coordinates), with
+    // the position and extent for `motion_absolute`. Version 2 maps the
+    // motion onto the game's output; version 1 onto the whole output layout.
+    fn create_virtual_pointer(
+        &self,
+        center: (i32, i32),
+    ) -> Option<VirtualPointerTarget> {
+        let app_data = self.app_data.as_ref()?;
+        let event_queue = self.event_queue.as_ref()?;
+        let manager = app_data.virtual_pointer_manager.as_ref()?;
+        let qh = event_queue.handle();
+        let (virtual_pointer, (x, y, width, height)) = match &self.lock_output {
+            Some((output, rect)) if manager.version() >= 2 => (
+                manager.create_virtual_pointer_with_output(app_data.seat.as_ref(), Some(output), &qh, ()),
+                *rect,
+            ),
+            _ => {
+                let layout = app_data
+                    .outputs
+                    .iter()
+                    .filter_map(Output::rect)
+                    .map(|(ox, oy, ow, oh)| (ox, oy, ox + ow, oy + oh))
+                    .reduce(|a, b| (a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3)))?;
+                (
+                    manager.create_virtual_pointer(app_data.seat.as_ref(), &qh, ()),
+                    (layout.0, layout.1, layout.2 - layout.0, layout.3 - layout.1),
+                )
+            }
+        };
+        let position = (
+            (center.0 - x).clamp(0, width.max(1) - 1) as u32,
+            (center.1 - y).clamp(0, height.max(1) - 1) as u32,
+        );
+        Some((virtual_pointer, position, (width.max(1) as u32, height.max(1) as u32)))
+    }
+
+    // Move the compositor's cursor to `center` (root coordinates) once with
+    // a wlroots virtual pointer, so the lock pins it there. Only done before
+    // locking: wlroots turns virtual pointer motion into relative motion on
+    // a locked pointer, which would reach the game as a jerk.
+    fn center_virtual_pointer(&self, center: (i32, i32)) {
+        let (Some((virtual_pointer, position, extent)), Some(event_queue)) =
+            (self.create_virtual_pointer(center), &self.event_queue)
+        else {
+            return;
+        };
+        debug!("Moving the cursor to {:?} of {:?} with a virtual pointer", position, extent);
+        virtual_pointer.motion_absolute(0, position.0, position.1, extent.0, extent.1);
+        virtual_pointer.frame();
+        virtual_pointer.destroy();
+        if let Err(e) = event_queue.flush() {
+            debug!("❌ Error flushing virtual pointer motion: {}", e);
+        }
+    }
+
+    // Leave the compositor's cursor at `center` (root coordinates) on unlock
+    // through the locked pointer's cursor position hint, and move it there
+    // now with wp_pointer_warp_v1 when bound and the pointer is on our
+    // surface. Returns whether the native warp was sent.
+    fn warp_native(&self, center: (i32, i32)) -> bool {
+        let (Some(app_data), Some(event_queue)) = (&self.app_data, &self.event_queue) else {
+            return false;
+        };
+        let (Some(locked_pointer), Some(lock_surface)) = (&app_data.locked_pointer, &app_data.lock_surface) else {
+            return false;
+        };
+        // Surface-local position; the lock surface covers the game's output
+        let origin = self.lock_origin();
+        let x = f64::from(center.0 - origin.0);
+        let y = f64::from(center.1 - origin.1);
+        let warped = match (&app_data.pointer_warp, &app_data.pointer, app_data.pointer_enter_serial) {
+            (Some(pointer_warp), Some(pointer), Some(serial)) => {
+                debug!("Warping pointer to ({}, {}) with wp_pointer_warp_v1", x, y);
+                pointer_warp.warp_pointer(lock_surface.surface(), pointer, x, y, serial);
+                true
+            }
+            _ => false,
+        };
+        debug!("Setting cursor position hint to ({}, {})", x, y);
+        locked_pointer.set_cursor_position_hint(x, y);
+        lock_surface.apply_constraint_state();
+        if let Err(e) = event_queue.flush() {
+            debug!("❌ Error flushing pointer warp: {}", e);
+            return false;
+        }
+        warped
+    }
+}
+
+// Warp the X pointer to `center` every `interval` until `stop` is set.
+// Runs on its own connection to `display_name` since Xlib connections
+// aren't shared across threads.
+fn warp_pointer_loop(
+    display_name: &std::ffi::CStr,
+    center: (i32, i32),
+    interval: std::time::Duration,
+    stop: &std::sync::atomic::AtomicBool,
+) {
+    unsafe {
+        let display = x11::xlib::XOpenDisplay(display_name.as_ptr());
+        if display.is_null() {
+            error!("Could not open X display for warping");
+            return;
+        }
+        let lost = std::sync::atomic::AtomicBool::new(false);
+        x11_watcher::survive_io_errors(display, &lost);
+        let screen = x11::xlib::XDefaultScreen(display);
+        let root = x11::xlib::XRootWindow(display, screen);
+        let (center_x, center_y) = center;
+        #[cfg(debug_assertions)]
+        {
+            debug!("Starting cursor warping thread to ({}, {})", center_x, center_y);
+        }
+        while !stop.load(std::sync::atomic::Ordering::Relaxed) {
+            if lost.load(std::sync::atomic::Ordering::Relaxed) {
+                warn!("X connection lost, stopping cursor warping");
+                break;
             }
+            x11::xlib::XWarpPointer(display, 0, root, 0, 0, 0, 0, center_x, center_y);
+            x11::xlib::XFlush(display);
+            std::thread::sleep(interval);
         }
+        x11::xlib::XCloseDisplay(display);
     }
 }
 
+// Watch the X11 connection fd and react to events as soon as they arrive
+fn add_x11_source(daemon_rc: &std::rc::Rc<std::cell::RefCell<PointerLockDaemon>>) {
+    let Some(fd) = daemon_rc.borrow().x11_fd() else {
+        return;
+    };
+    let daemon_x11 = daemon_rc.clone();
+    let condition = glib::IOCondition::IN | glib::IOCondition::HUP | glib::IOCondition::ERR;
+    let source = glib::source::unix_fd_add_local(fd, condition, move |_, condition| {
+        if condition.intersects(glib::IOCondition::HUP | glib::IOCondition::ERR) {
+            daemon_x11.borrow_mut().handle_x11_lost();
+            return glib::Continue(false);
+        }
+        daemon_x11.borrow_mut().process_x11_events();
+        schedule_recheck(&daemon_x11);
+        glib::Continue(true)
+    });
+    daemon_rc.borrow_mut().x11_source = Some(source);
+}
+
+// Dispatch Wayland events as soon as they arrive on the socket
+fn add_wayland_source(daemon_rc: &std::rc::Rc<std::cell::RefCell<PointerLockDaemon>>) {
+    let Some(fd) = daemon_rc.borrow().wayland_fd() else {
+        return;
+    };
+    let daemon_wayland = daemon_rc.clone();
+    let condition = glib::IOCondition::IN | glib::IOCondition::HUP | glib::IOCondition::ERR;
+    let source = glib::source::unix_fd_add_local(fd, condition, move |_, condition| {
+        if condition.intersects(glib::IOCondition::HUP | glib::IOCondition::ERR) {
+            daemon_wayland.borrow_mut().handle_wayland_lost();
+            arm_wayland_reconnect(&daemon_wayland);
+            return glib::Continue(false);
+        }
+        daemon_wayland.borrow_mut().process_wayland_events();
+        arm_wayland_reconnect(&daemon_wayland);
+        glib::Continue(true)
+    });
+    daemon_rc.borrow_mut().wayland_source = Some(source);
+}
+
+// (Re)connect to the X server whenever there is no connection, e.g. after
+// XWayland restarted or when it wasn't up yet at startup
+fn add_x11_reconnect_timer(daemon_rc: &std::rc::Rc<std::cell::RefCell<PointerLockDaemon>>) {
+    let daemon_rc = daemon_rc.clone();
+    glib::timeout_add_local(X11_RECONNECT_INTERVAL, move || {
+        if daemon_rc.borrow().x11_watcher.is_some() {
+            return glib::Continue(true);
+        }
+        let watcher = PointerLockDaemon::open_x11(&mut daemon_rc.borrow_mut().xwayland);
+        if let Some(watcher) = watcher {
+            daemon_rc.borrow_mut().x11_watcher = Some(watcher);
+            add_x11_source(&daemon_rc);
+            daemon_rc.borrow_mut().update();
+            schedule_recheck(&daemon_rc);
+        }
+        glib::Continue(true)
+    });
+}
+
+// Start reconnecting to the compositor if the connection is gone (a failed
+// startup or a lost connection) and no reconnect timer is armed yet
+fn arm_wayland_reconnect(daemon_rc: &std::rc::Rc<std::cell::RefCell<PointerLockDaemon>>) {
+    {
+        let mut daemon = daemon_rc.borrow_mut();
+        if daemon.app_data.is_some() || daemon.wayland_reconnecting {
+            return;
+        }
+        daemon.wayland_reconnecting = true;
+    }
+    add_wayland_reconnect_timer(daemon_rc, WAYLAND_RECONNECT_MIN);
+}
+
+// Try to reconnect after `delay`, backing off from WAYLAND_RECONNECT_MIN to
+// WAYLAND_RECONNECT_MAX while the compositor stays down; stops once connected
+fn add_wayland_reconnect_timer(
+    daemon_rc: &std::rc::Rc<std::cell::RefCell<PointerLockDaemon>>,
+    delay: std::time::Duration,
+) {
+    let daemon_rc = daemon_rc.clone();
+    glib::timeout_add_local_once(delay, move || {
+        let connected = {
+            let mut daemon = daemon_rc.borrow_mut();
+            daemon.app_data.is_some() || daemon.reconnect_wayland()
+        };
+        if !connected {
+            add_wayland_reconnect_timer(&daemon_rc, (delay * 2).min(WAYLAND_RECONNECT_MAX));
+            return;
+        }
+        daemon_rc.borrow_mut().wayland_reconnecting = false;
+        add_wayland_source(&daemon_rc);
+        daemon_rc.borrow_mut().update();
+        schedule_recheck(&daemon_rc);
+    });
+}
+
+// Periodic re-evaluation for state not covered by X11 events; the timer
+// replaces itself when the interval it should run at changes
+fn add_poll_timer(daemon_rc: &std::rc::Rc<std::cell::RefCell<PointerLockDaemon>>) {
+    let interval = daemon_rc.borrow().poll_interval();
+    let daemon_poll = daemon_rc.clone();
+    let source = glib::timeout_add_local(interval, move || {
+        daemon_poll.borrow_mut().update();
+        schedule_recheck(&daemon_poll);
+        if daemon_poll.borrow().poll_interval() != interval {
+            add_poll_timer(&daemon_poll);
+            return glib::Continue(false);
+        }
+        glib::Continue(true)
+    });
+    daemon_rc.borrow_mut().poll_source = Some(source);
+}
+
+// Re-run the update once a pending lock/unlock debounce delay has elapsed,
+// so transitions don't wait for the next poll tick
+fn schedule_recheck(daemon_rc: &std::rc::Rc<std::cell::RefCell<PointerLockDaemon>>) {
+    // Every update passes through here and may have lost the compositor
+    arm_wayland_reconnect(daemon_rc);
+    let delay = {
+        let mut daemon = daemon_rc.borrow_mut();
+        if daemon.recheck_scheduled {
+            return;
+        }
+        match daemon.pending_remaining() {
+            Some(remaining) => {
+                daemon.recheck_scheduled = true;
+                remaining
+            }
+            None => return,
+        }
+    };
+    let daemon_rc = daemon_rc.clone();
+    glib::timeout_add_local_once(delay, move || {
+        {
+            let mut daemon = daemon_rc.borrow_mut();
+            daemon.recheck_scheduled = false;
+            daemon.update();
+        }
+        schedule_recheck(&daemon_rc);
+    });
+}
+
 fn main() {
     // Init logger: default to debug in debug builds, info otherwise; allow RUST_LOG to override
     #[cfg(debug_assertions)]
@@ -896,7 +2370,18 @@ fn main() {
 
     info!("Starting demonhide daemon...");
 
-    let daemon = match PointerLockDaemon::new() {
+    let config = Config::load_or_default();
+
+    // Xlib is used from the warp thread and the discovery changes its global
+    // authorization; make Xlib lock around both before any other call
+    unsafe {
+        x11::xlib::XInitThreads();
+    }
+
+    // Survive BadWindow races and XWayland restarts instead of exiting
+    x11_watcher::install_error_handlers();
+
+    let daemon = match PointerLockDaemon::new(config.clone()) {
         Ok(daemon) => daemon,
         Err(e) => {
             eprintln!("Failed to initialize daemon: {}", e);
@@ -912,14 +2397,248 @@ fn main() {
     use std::cell::RefCell;
     use std::rc::Rc;
     let daemon_rc = Rc::new(RefCell::new(daemon));
-    let daemon_clone = daemon_rc.clone();
 
-    glib::timeout_add_local(std::time::Duration::from_millis(500), move || {
-        let mut daemon = daemon_clone.borrow_mut();
-        daemon.update();
-        glib::Continue(true)
-    });
+    // React to XFixes cursor notifications and Wayland events as soon as they arrive
+    add_x11_source(&daemon_rc);
+    add_wayland_source(&daemon_rc);
+    add_x11_reconnect_timer(&daemon_rc);
+    arm_wayland_reconnect(&daemon_rc);
+
+    add_poll_timer(&daemon_rc);
+
+    // Reload the config on change; keep the old one if the new one is invalid
+    if let Some(mut config_watcher) = ConfigWatcher::new() {
+        let daemon_cfg = daemon_rc.clone();
+        glib::source::unix_fd_add_local(config_watcher.fd(), glib::IOCondition::IN, move |_, _| {
+            if !config_watcher.has_changes() {
+                return glib::Continue(true);
+            }
+            match Config::load() {
+                Ok(config) => {
+                    let poll_interval = daemon_cfg.borrow().poll_interval();
+                    daemon_cfg.borrow_mut().apply_config(config);
+                    // The poll timer's interval is fixed at creation, so replace it
+                    if daemon_cfg.borrow().poll_interval() != poll_interval {
+                        if let Some(source) = daemon_cfg.borrow_mut().poll_source.take() {
+                            source.remove();
+                        }
+                        add_poll_timer(&daemon_cfg);
+                    }
+                    daemon_cfg.borrow_mut().update();
+                    schedule_recheck(&daemon_cfg);
+                }
+                Err(e) => error!("Failed to reload configuration, keeping previous: {}", e),
+            }
+            glib::Continue(true)
+        });
+    }
 
     info!("Starting main loop...");
     loop_.run();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rect_covers_exact_and_larger_windows() {
+        let monitor = (1920, 0, 2560, 1440);
+        assert!(rect_covers((1920, 0, 2560, 1440), monitor, 0));
+        assert!(rect_covers((1900, -20, 2600, 1480), monitor, 0));
+        assert!(!rect_covers((0, 0, 1920, 1080), monitor, 0));
+        assert!(!rect_covers((1920, 0, 2560, 1439), monitor, 0));
+    }
+
+    #[test]
+    fn rect_covers_within_tolerance() {
+        let monitor = (0, 0, 1920, 1080);
+        // Wine's decoration-sized shortfall on the top and bottom edges
+        assert!(rect_covers((0, 30, 1920, 1020), monitor, 64));
+        assert!(!rect_covers((0, 30, 1920, 1020), monitor, 16));
+        assert!(!rect_covers((100, 0, 1820, 1080), monitor, 64));
+        // Degenerate monitors are never covered
+        assert!(!rect_covers((0, 0, 1920, 1080), (0, 0, 0, 1080), 0));
+    }
+
+    const DELAYS: (std::time::Duration, std::time::Duration) =
+        (std::time::Duration::from_millis(250), std::time::Duration::from_millis(400));
+
+    fn detection(should_lock: bool, window: x11::xlib::Window) -> Detection {
+        Detection { should_lock, window: Some(window), ..Detection::default() }
+    }
+
+    fn unlocked() -> LockState {
+        LockState { requested: false, window: None }
+    }
+
+    fn locked(window: x11::xlib::Window) -> LockState {
+        LockState { requested: true, window: Some(window) }
+    }
+
+    #[test]
+    fn locks_once_the_lock_delay_has_passed() {
+        let start = std::time::Instant::now();
+        let (action, pending) = decide_lock(&detection(true, 1), unlocked(), None, start, DELAYS);
+        assert_eq!((action, pending), (LockAction::Keep, Some((true, start))));
+        let later = start + std::time::Duration::from_millis(100);
+        let (action, pending) = decide_lock(&detection(true, 1), unlocked(), pending, later, DELAYS);
+        assert_eq!((action, pending), (LockAction::Keep, Some((true, start))));
+        let later = start + DELAYS.0;
+        assert_eq!(decide_lock(&detection(true, 1), unlocked(), pending, later, DELAYS), (LockAction::Lock, None));
+    }
+
+    #[test]
+    fn flapping_inside_the_delay_never_locks() {
+        let start = std::time::Instant::now();
+        let step = std::time::Duration::from_millis(100);
+        let mut pending = None;
+        for i in 0..10 {
+            let now = start + step * i;
+            let (action, next) = decide_lock(&detection(i % 2 == 0, 1), unlocked(), pending, now, DELAYS);
+            assert_eq!(action, LockAction::Keep);
+            pending = next;
+        }
+    }
+
+    #[test]
+    fn focus_leaving_the_locked_window_unlocks_immediately() {
+        let now = std::time::Instant::now();
+        let (action, pending) = decide_lock(&detection(false, 2), locked(1), None, now, DELAYS);
+        assert_eq!((action, pending), (LockAction::UnlockNow, None));
+        // A new window that qualifies starts its own lock delay
+        let (action, pending) = decide_lock(&detection(true, 2), locked(1), None, now, DELAYS);
+        assert_eq!((action, pending), (LockAction::UnlockNow, Some((true, now))));
+    }
+
+    #[test]
+    fn unlocks_after_the_unlock_delay_when_the_cursor_reappears() {
+        let start = std::time::Instant::now();
+        let (action, pending) = decide_lock(&detection(false, 1), locked(1), None, start, DELAYS);
+        assert_eq!((action, pending), (LockAction::Keep, Some((false, start))));
+        let later = start + DELAYS.0;
+        let (action, pending) = decide_lock(&detection(false, 1), locked(1), pending, later, DELAYS);
+        assert_eq!((action, pending), (LockAction::Keep, Some((false, start))));
+        let later = start + DELAYS.1;
+        assert_eq!(decide_lock(&detection(false, 1), locked(1), pending, later, DELAYS), (LockAction::Unlock, None));
+    }
+
+    #[test]
+    fn quarter_turns_swap_axes() {
+        assert!(transform_swaps_axes(wl_output::Transform::_90));
+        assert!(transform_swaps_axes(wl_output::Transform::_270));
+        assert!(transform_swaps_axes(wl_output::Transform::Flipped90));
+        assert!(transform_swaps_axes(wl_output::Transform::Flipped270));
+        assert!(!transform_swaps_axes(wl_output::Transform::Normal));
+        assert!(!transform_swaps_axes(wl_output::Transform::_180));
+        assert!(!transform_swaps_axes(wl_output::Transform::Flipped));
+        assert!(!transform_swaps_axes(wl_output::Transform::Flipped180));
+    }
+
+    // Output with a wl_output mode and no xdg-output geometry; the proxy is
+    // inert, on a connection that never talks to a compositor
+    fn mode_only_output(mode: MonitorRect, transform: wl_output::Transform) -> Output {
+        let (socket, _peer) = std::os::unix::net::UnixStream::pair().unwrap();
+        let conn = Connection::from_socket(socket).unwrap();
+        Output {
+            output: wl_output::WlOutput::inert(conn.backend().downgrade()),
+            info: std::sync::Arc::new(std::sync::Mutex::new(Some(mode))),
+            transform,
+            xdg_output: None,
+            logical: std::sync::Arc::new(std::sync::Mutex::new(None)),
+        }
+    }
+
+    #[test]
+    fn output_rect_rotates_mode_without_xdg_output() {
+        let mode = (1920, 0, 2560, 1440, 2);
+        for transform in [wl_output::Transform::_90, wl_output::Transform::_270] {
+            assert_eq!(mode_only_output(mode, transform).rect(), Some((1920, 0, 720, 1280)));
+        }
+        assert_eq!(mode_only_output(mode, wl_output::Transform::Normal).rect(), Some((1920, 0, 1280, 720)));
+    }
+
+    #[test]
+    fn output_rect_prefers_xdg_output_geometry() {
+        let output = mode_only_output((0, 0, 3840, 2160, 1), wl_output::Transform::_90);
+        *output.logical.lock().unwrap() = Some((0, 0, 1440, 2560));
+        assert_eq!(output.rect(), Some((0, 0, 1440, 2560)));
+    }
+
+    #[test]
+    fn monitors_xml_rotation_swaps_size() {
+        let xml = r#"<monitors version="2">
+  <configuration>
+    <logicalmonitor>
+      <x>0</x>
+      <y>0</y>
+      <scale>1</scale>
+      <primary>yes</primary>
+      <monitor>
+        <monitorspec><connector>DP-1</connector></monitorspec>
+        <mode><width>2560</width><height>1440</height><rate>144</rate></mode>
+      </monitor>
+    </logicalmonitor>
+    <logicalmonitor>
+      <x>2560</x>
+      <y>0</y>
+      <scale>2</scale>
+      <transform><rotation>left</rotation></transform>
+      <monitor>
+        <monitorspec><connector>HDMI-1</connector></monitorspec>
+        <mode><width>1920</width><height>1080</height><rate>60</rate></mode>
+      </monitor>
+    </logicalmonitor>
+  </configuration>
+</monitors>"#;
+        assert_eq!(
+            parse_monitors_xml(xml),
+            vec![(0, 0, 2560, 1440), (2560, 0, 540, 960)]
+        );
+    }
+
+    #[test]
+    fn monitors_xml_reads_only_the_first_configuration() {
+        let xml = r#"<monitors version="2">
+  <configuration>
+    <logicalmonitor>
+      <x>0</x>
+      <y>0</y>
+      <scale>1</scale>
+      <monitor>
+        <monitorspec><connector>eDP-1</connector></monitorspec>
+        <mode><width>1920</width><height>1200</height><rate>60</rate></mode>
+      </monitor>
+    </logicalmonitor>
+    <disabled>
+      <monitorspec><connector>DP-1</connector></monitorspec>
+    </disabled>
+  </configuration>
+  <configuration>
+    <logicalmonitor>
+      <x>0</x>
+      <y>0</y>
+      <scale>1</scale>
+      <monitor>
+        <monitorspec><connector>DP-1</connector></monitorspec>
+        <mode><width>3840</width><height>2160</height><rate>60</rate></mode>
+      </monitor>
+    </logicalmonitor>
+  </configuration>
+</monitors>"#;
+        assert_eq!(parse_monitors_xml(xml), vec![(0, 0, 1920, 1200)]);
+    }
+
+    #[test]
+    fn monitors_xml_fractional_scale() {
+        let xml = "<logicalmonitor><x>0</x><y>0</y><scale>1.5</scale>\
+                   <monitor><mode><width>2880</width><height>1620</height></mode></monitor>\
+                   </logicalmonitor>";
+        assert_eq!(parse_monitors_xml(xml), vec![(0, 0, 1920, 1080)]);
+    }
+
+    #[test]
+    fn monitors_xml_without_monitors_is_empty() {
+        assert!(parse_monitors_xml("<monitors version=\"2\"></monitors>").is_empty());
+    }
+}
@@ -0,0 +1,235 @@
+use log::{debug, info, warn};
+use std::ffi::CStr;
+use std::os::raw::{c_int, c_uint};
+use std::sync::atomic::{AtomicBool, Ordering};
+// libXtst 1.2.4 gave XTestFakeRelativeMotionEvent the screen argument its
+// header always declared, which these bindings follow; older releases
+// would read dx as the screen
+use x11::xtest::{
+    XTestFakeButtonEvent, XTestFakeKeyEvent, XTestFakeMotionEvent, XTestFakeRelativeMotionEvent,
+    XTestQueryExtension,
+};
+
+// Linux input event codes (<linux/input-event-codes.h>) of the mouse buttons
+const BTN_LEFT: u32 = 0x110;
+const BTN_RIGHT: u32 = 0x111;
+const BTN_MIDDLE: u32 = 0x112;
+const BTN_SIDE: u32 = 0x113;
+// Scroll distance XWayland turns into one wheel click
+const AXIS_STEP: f64 = 10.0;
+// XTest motion on screen -1 happens on the pointer's current screen
+const CURRENT_SCREEN: c_int = -1;
+// X keycodes are evdev key codes offset by 8
+const KEYCODE_OFFSET: u32 = 8;
+
+/// Injects input into XWayland through XTest: relative or absolute motion,
+/// buttons and scrolling, plus keys while our surface has keyboard focus,
+/// so games keep getting input while the Wayland pointer is held on our
+/// surface.
+pub struct MotionForwarder {
+    display: *mut x11::xlib::Display,
+    // Game window keys are meant for
+    window: x11::xlib::Window,
+    // Sub-pixel motion carried over to the next event
+    remainder: (f64, f64),
+    // Scroll distance short of a whole wheel click, (horizontal, vertical)
+    axis_remainder: (f64, f64),
+    // X buttons pressed through us, released when forwarding stops
+    pressed: Vec<c_uint>,
+    // X keycodes pressed through us, released with the keyboard focus
+    pressed_keys: Vec<c_uint>,
+    // Boxed so the address handed to Xlib stays stable
+    lost: Box<AtomicBool>,
+}
+
+impl MotionForwarder {
+    pub fn open(display_name: &CStr, window: x11::xlib::Window) -> Option<Self> {
+        unsafe {
+            let display = x11::xlib::XOpenDisplay(display_name.as_ptr());
+            if display.is_null() {
+                debug!("Could not open X display for motion forwarding");
+                return None;
+            }
+            let lost = Box::new(AtomicBool::new(false));
+            crate::x11_watcher::survive_io_errors(display, &lost);
+            let (mut event_base, mut error_base, mut major, mut minor) = (0, 0, 0, 0);
+            if XTestQueryExtension(
+                display,
+                &mut event_base,
+                &mut error_base,
+                &mut major,
+                &mut minor,
+            ) == 0
+            {
+                info!("XTest extension not available, pointer input will not be forwarded");
+                x11::xlib::XCloseDisplay(display);
+                return None;
+            }
+            debug!("XTest {}.{} available for input forwarding", major, minor);
+            Some(MotionForwarder {
+                display,
+                window,
+                remainder: (0.0, 0.0),
+                axis_remainder: (0.0, 0.0),
+                pressed: Vec::new(),
+                pressed_keys: Vec::new(),
+                lost,
+            })
+        }
+    }
+
+    /// Move the X pointer by (`dx`, `dy`), keeping fractional parts for later
+    pub fn forward(&mut self, dx: f64, dy: f64) {
+        if self.lost.load(Ordering::Relaxed) {
+            return;
+        }
+        let x = self.remainder.0 + dx;
+        let y = self.remainder.1 + dy;
+        let (whole_x, whole_y) = (x.trunc(), y.trunc());
+        self.remainder = (x - whole_x, y - whole_y);
+        if whole_x == 0.0 && whole_y == 0.0 {
+            return;
+        }
+        unsafe {
+            XTestFakeRelativeMotionEvent(self.display, CURRENT_SCREEN, whole_x as c_int, whole_y as c_int, 0);
+        }
+        self.flush();
+    }
+
+    /// Move the X pointer to (`x`, `y`) in root coordinates, for confinement
+    /// where the compositor still moves the cursor over our surface
+    pub fn move_to(&mut self, x: f64, y: f64) {
+        if self.lost.load(Ordering::Relaxed) {
+            return;
+        }
+        self.remainder = (0.0, 0.0);
+        unsafe {
+            XTestFakeMotionEvent(self.display, CURRENT_SCREEN, x.round() as c_int, y.round() as c_int, 0);
+        }
+        self.flush();
+    }
+
+    /// Press or release the X button matching the evdev `button` code,
+    /// numbered the way XWayland does
+    pub fn button(&mut self, button: u32, pressed: bool) {
+        let x_button = match button {
+            BTN_LEFT => 1,
+            BTN_MIDDLE => 2,
+            BTN_RIGHT => 3,
+            _ if button >= BTN_SIDE => button - BTN_SIDE + 8,
+            _ => return,
+        };
+        if pressed {
+            self.pressed.push(x_button);
+        } else {
+            self.pressed.retain(|b| *b != x_button);
+        }
+        self.fake_button(x_button, pressed);
+        self.flush();
+    }
+
+    /// Scroll by `value` (wl_pointer axis units) as wheel clicks: buttons
+    /// 4/5 vertically and 6/7 horizontally
+    pub fn axis(&mut self, horizontal: bool, value: f64) {
+        let (remainder, back, forward) = if horizontal {
+            (&mut self.axis_remainder.0, 6, 7)
+        } else {
+            (&mut self.axis_remainder.1, 4, 5)
+        };
+        let total = *remainder + value;
+        let steps = (total / AXIS_STEP).trunc();
+        *remainder = total - steps * AXIS_STEP;
+        if steps == 0.0 {
+            return;
+        }
+        let x_button = if steps < 0.0 { back } else { forward };
+        for _ in 0..steps.abs() as u32 {
+            self.fake_button(x_button, true);
+            self.fake_button(x_button, false);
+        }
+        self.flush();
+    }
+
+    /// Press or release the X key matching the evdev `key` code
+    pub fn key(&mut self, key: u32, pressed: bool) {
+        let keycode = key + KEYCODE_OFFSET;
+        if pressed {
+            self.pressed_keys.push(keycode);
+        } else {
+            self.pressed_keys.retain(|k| *k != keycode);
+        }
+        self.fake_key(keycode, pressed);
+        self.flush();
+    }
+
+    /// Release every key pressed through us, e.g. when keyboard focus leaves
+    pub fn release_keys(&mut self) {
+        for keycode in std::mem::take(&mut self.pressed_keys) {
+            self.fake_key(keycode, false);
+        }
+        self.flush();
+    }
+
+    /// Give the game window X input focus so forwarded keys reach it; the
+    /// window manager moves X focus away while our surface is focused
+    pub fn focus_window(&self) {
+        if self.lost.load(Ordering::Relaxed) {
+            return;
+        }
+        unsafe {
+            x11::xlib::XSetInputFocus(
+                self.display,
+                self.window,
+                x11::xlib::RevertToParent,
+                x11::xlib::CurrentTime,
+            );
+        }
+        self.flush();
+    }
+
+    fn fake_key(&self, keycode: c_uint, pressed: bool) {
+        if self.lost.load(Ordering::Relaxed) {
+            return;
+        }
+        unsafe {
+            XTestFakeKeyEvent(self.display, keycode, c_int::from(pressed), 0);
+        }
+    }
+
+    fn fake_button(&self, x_button: c_uint, pressed: bool) {
+        if self.lost.load(Ordering::Relaxed) {
+            return;
+        }
+        unsafe {
+            XTestFakeButtonEvent(self.display, x_button, c_int::from(pressed), 0);
+        }
+    }
+
+    fn flush(&self) {
+        if self.lost.load(Ordering::Relaxed) {
+            return;
+        }
+        unsafe {
+            x11::xlib::XFlush(self.display);
+        }
+        if self.lost.load(Ordering::Relaxed) {
+            warn!("X connection lost, no longer forwarding input");
+        }
+    }
+}
+
+impl Drop for MotionForwarder {
+    fn drop(&mut self) {
+        // A button or key held when forwarding stops would otherwise stay down in X
+        for x_button in std::mem::take(&mut self.pressed) {
+            self.fake_button(x_button, false);
+        }
+        for keycode in std::mem::take(&mut self.pressed_keys) {
+            self.fake_key(keycode, false);
+        }
+        self.flush();
+        unsafe {
+            x11::xlib::XCloseDisplay(self.display);
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use crate::window_info::WindowInfo;
+use regex::Regex;
+use serde::Deserialize;
+
+/// What to do when a rule matches the focused window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    /// Lock whenever the window is focused, whatever the heuristics say
+    Always,
+    /// Never lock this window
+    Never,
+    /// Stop evaluating rules and let the fullscreen/cursor heuristic decide
+    Heuristic,
+}
+
+/// How the pointer is constrained while a window qualifies
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConstraintMode {
+    /// Lock the pointer in place and keep the cursor centred (mouse-look)
+    #[default]
+    Lock,
+    /// Keep a visible cursor inside the window rectangle (edge scrolling)
+    Confine,
+}
+
+// On-disk representation of a `[[rules]]` entry
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleFile {
+    name: Option<String>,
+    instance: Option<String>,
+    class: Option<String>,
+    title: Option<String>,
+    executable: Option<String>,
+    steam_app_id: Option<u32>,
+    action: RuleAction,
+    mode: Option<ConstraintMode>,
+}
+
+/// A compiled per-application rule. Every matcher that is set must match;
+/// rules are evaluated in order and the first match wins.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub name: String,
+    instance: Option<String>,
+    class: Option<String>,
+    title: Option<Regex>,
+    executable: Option<String>,
+    steam_app_id: Option<u32>,
+    pub action: RuleAction,
+    pub mode: ConstraintMode,
+}
+
+impl Rule {
+    // `origin` (the config file) and `index` only label rules that have no
+    // explicit name, so unnamed rules from different files stay apart
+    pub fn compile(file: RuleFile, origin: &str, index: usize) -> Result<Self, String> {
+        let name = file
+            .name
+            .unwrap_or_else(|| format!("{} rule #{}", origin, index + 1));
+        if file.instance.is_none()
+            && file.class.is_none()
+            && file.title.is_none()
+            && file.executable.is_none()
+            && file.steam_app_id.is_none()
+        {
+            return Err(format!("{}: rule has no matchers", name));
+        }
+        let title = match file.title {
+            Some(pattern) => Some(
+                Regex::new(&pattern)
+                    .map_err(|e| format!("{}: invalid title regex: {}", name, e))?,
+            ),
+            None => None,
+        };
+        Ok(Rule {
+            name,
+            instance: file.instance,
+            class: file.class,
+            title,
+            executable: file.executable,
+            steam_app_id: file.steam_app_id,
+            action: file.action,
+            mode: file.mode.unwrap_or_default(),
+        })
+    }
+
+    fn matches(&self, info: &WindowInfo) -> bool {
+        let eq_ignore_case = |expected: &Option<String>, actual: &Option<String>| match expected {
+            Some(expected) => actual
+                .as_ref()
+                .is_some_and(|actual| actual.eq_ignore_ascii_case(expected)),
+            None => true,
+        };
+        if !eq_ignore_case(&self.instance, &info.instance) {
+            return false;
+        }
+        if !eq_ignore_case(&self.class, &info.class) {
+            return false;
+        }
+        if let Some(title) = &self.title {
+            if !info.title.as_ref().is_some_and(|t| title.is_match(t)) {
+                return false;
+            }
+        }
+        if let Some(executable) = &self.executable {
+            // Accept either the full path or just the file name
+            let matched = info.executable.as_ref().is_some_and(|exe| {
+                exe.to_str() == Some(executable.as_str())
+                    || exe.file_name().and_then(|n| n.to_str()) == Some(executable.as_str())
+            });
+            if !matched {
+                return false;
+            }
+        }
+        if let Some(app_id) = self.steam_app_id {
+            if info.steam_app_id() != Some(app_id) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Return the first rule matching `info`
+pub fn first_match<'a>(rules: &'a [Rule], info: &WindowInfo) -> Option<&'a Rule> {
+    rules.iter().find(|rule| rule.matches(info))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::steam::{SteamGame, SteamSource};
+
+    fn rule(name: &str, contents: &str) -> Rule {
+        let file: RuleFile = toml::from_str(contents).expect("valid rule");
+        Rule::compile(
+            RuleFile {
+                name: Some(name.to_string()),
+                ..file
+            },
+            "test.toml",
+            0,
+        )
+        .expect("rule compiles")
+    }
+
+    fn game() -> WindowInfo {
+        WindowInfo {
+            instance: Some("game.exe".into()),
+            class: Some("Game.exe".into()),
+            title: Some("Some Game - Main Menu".into()),
+            executable: Some("/usr/bin/wine64-preloader".into()),
+            steam: Some(SteamGame {
+                app_id: Some(570),
+                source: SteamSource::ProcessEnvironment,
+            }),
+            is_wine: true,
+        }
+    }
+
+    #[test]
+    fn every_set_matcher_must_match() {
+        let info = game();
+        assert!(rule("class", "class = \"game.EXE\"\naction = \"always\"").matches(&info));
+        assert!(rule("title", "title = \"^Some Game\"\naction = \"always\"").matches(&info));
+        assert!(rule(
+            "exe",
+            "executable = \"wine64-preloader\"\naction = \"always\""
+        )
+        .matches(&info));
+        assert!(rule(
+            "path",
+            "executable = \"/usr/bin/wine64-preloader\"\naction = \"always\""
+        )
+        .matches(&info));
+        assert!(rule("app", "steam_app_id = 570\naction = \"always\"").matches(&info));
+        assert!(!rule(
+            "both",
+            "class = \"game.exe\"\nsteam_app_id = 440\naction = \"always\""
+        )
+        .matches(&info));
+        assert!(!rule("title", "title = \"^Main Menu\"\naction = \"always\"").matches(&info));
+        assert!(!rule("wine", "executable = \"wine\"\naction = \"always\"").matches(&info));
+    }
+
+    #[test]
+    fn missing_properties_do_not_match() {
+        let info = WindowInfo::default();
+        assert!(!rule("class", "class = \"game.exe\"\naction = \"never\"").matches(&info));
+        assert!(!rule("title", "title = \".*\"\naction = \"never\"").matches(&info));
+        assert!(!rule("app", "steam_app_id = 570\naction = \"never\"").matches(&info));
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules = [
+            rule("other", "class = \"other\"\naction = \"never\""),
+            rule("first", "instance = \"game.exe\"\naction = \"heuristic\""),
+            rule(
+                "second",
+                "class = \"game.exe\"\naction = \"always\"\nmode = \"confine\"",
+            ),
+        ];
+        let matched = first_match(&rules, &game()).expect("a rule matches");
+        assert_eq!(matched.name, "first");
+        assert_eq!(matched.action, RuleAction::Heuristic);
+        assert_eq!(matched.mode, ConstraintMode::Lock);
+        assert_eq!(
+            first_match(&rules[2..], &game()).unwrap().mode,
+            ConstraintMode::Confine
+        );
+        assert!(first_match(&rules[..1], &game()).is_none());
+    }
+
+    #[test]
+    fn rules_need_a_matcher() {
+        let file: RuleFile = toml::from_str("action = \"always\"").unwrap();
+        assert!(Rule::compile(file, "config.toml", 2)
+            .unwrap_err()
+            .starts_with("config.toml rule #3"));
+    }
+}
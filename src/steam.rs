@@ -0,0 +1,182 @@
+use crate::x11_watcher;
+use log::debug;
+
+// How far up the process tree to look for Steam's reaper
+const MAX_PARENT_DEPTH: usize = 32;
+
+/// How a window was identified as a Steam game
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SteamSource {
+    /// `STEAM_GAME` property on the client or focused window
+    WindowProperty,
+    /// `SteamAppId`/`SteamGameId` in the window process environment
+    ProcessEnvironment,
+    /// The window process runs under Steam's `reaper`
+    Reaper,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SteamGame {
+    /// Steam AppID when known (0 and non-numeric ids are dropped)
+    pub app_id: Option<u32>,
+    pub source: SteamSource,
+}
+
+/// Identify a Steam game from the `STEAM_GAME` property (`atom`) on any of
+/// `windows`, then from the environment and ancestry of the window process
+/// `pid`.
+pub fn identify(
+    display: *mut x11::xlib::Display,
+    atom: x11::xlib::Atom,
+    windows: &[x11::xlib::Window],
+    pid: Option<u32>,
+) -> Option<SteamGame> {
+    if atom != 0 {
+        for &window in windows {
+            match x11_watcher::read_property_u32s(display, window, atom) {
+                Some(values) if values.is_empty() => continue,
+                Some(values) => {
+                    let app_id = values.first().map(|v| *v as u32).filter(|id| *id != 0);
+                    debug!("STEAM_GAME={:?} on window {}", app_id, window);
+                    return Some(SteamGame {
+                        app_id,
+                        source: SteamSource::WindowProperty,
+                    });
+                }
+                // Not a CARDINAL: the property being set still marks a Steam game
+                None if x11_watcher::has_property(display, window, atom) => {
+                    debug!("STEAM_GAME set on window {} but not as CARDINAL", window);
+                    return Some(SteamGame {
+                        app_id: None,
+                        source: SteamSource::WindowProperty,
+                    });
+                }
+                None => {}
+            }
+        }
+    }
+
+    let pid = pid?;
+    if let Some(app_id) = app_id_from_environment(pid) {
+        debug!("Steam AppID {:?} from environment of pid {}", app_id, pid);
+        return Some(SteamGame {
+            app_id,
+            source: SteamSource::ProcessEnvironment,
+        });
+    }
+    if let Some(app_id) = reaper_ancestor(pid) {
+        debug!("pid {} runs under Steam reaper (AppID {:?})", pid, app_id);
+        return Some(SteamGame {
+            app_id,
+            source: SteamSource::Reaper,
+        });
+    }
+    None
+}
+
+// Some(app_id) when SteamAppId or SteamGameId is set in the process environment
+fn app_id_from_environment(pid: u32) -> Option<Option<u32>> {
+    let environ = std::fs::read(format!("/proc/{}/environ", pid)).ok()?;
+    parse_environment(&environ)
+}
+
+// Same, for the NUL-separated contents of /proc/<pid>/environ
+fn parse_environment(environ: &[u8]) -> Option<Option<u32>> {
+    let mut found = None;
+    for entry in environ.split(|b| *b == 0) {
+        let entry = String::from_utf8_lossy(entry);
+        let Some((key, value)) = entry.split_once('=') else {
+            continue;
+        };
+        match key {
+            // SteamAppId is the canonical one; prefer it when both are set
+            "SteamAppId" => return Some(parse_app_id(value)),
+            "SteamGameId" => found = Some(parse_app_id(value)),
+            _ => {}
+        }
+    }
+    found
+}
+
+// Walk parent processes looking for Steam's reaper. Returns Some(app_id),
+// with the AppID taken from reaper's `AppId=` argument when present.
+fn reaper_ancestor(pid: u32) -> Option<Option<u32>> {
+    let mut current = parent_pid(pid)?;
+    for _ in 0..MAX_PARENT_DEPTH {
+        if current <= 1 {
+            return None;
+        }
+        let comm = std::fs::read_to_string(format!("/proc/{}/comm", current)).ok()?;
+        if comm.trim() == "reaper" {
+            let cmdline = std::fs::read(format!("/proc/{}/cmdline", current)).unwrap_or_default();
+            let app_id = cmdline
+                .split(|b| *b == 0)
+                .filter_map(|arg| std::str::from_utf8(arg).ok())
+                .find_map(|arg| arg.strip_prefix("AppId="))
+                .and_then(parse_app_id);
+            return Some(app_id);
+        }
+        current = parent_pid(current)?;
+    }
+    None
+}
+
+// Parent pid from /proc/<pid>/stat
+fn parent_pid(pid: u32) -> Option<u32> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    parse_parent_pid(&stat)
+}
+
+// Field 4 of a stat line, after the parenthesised comm (which may itself
+// contain spaces and parentheses)
+fn parse_parent_pid(stat: &str) -> Option<u32> {
+    let after_comm = &stat[stat.rfind(')')? + 1..];
+    after_comm.split_whitespace().nth(1)?.parse().ok()
+}
+
+// Non-Steam shortcuts use 64-bit game ids; keep only real 32-bit AppIDs
+fn parse_app_id(value: &str) -> Option<u32> {
+    value.trim().parse::<u32>().ok().filter(|id| *id != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn app_ids_must_be_nonzero_32_bit() {
+        assert_eq!(parse_app_id("570"), Some(570));
+        assert_eq!(parse_app_id(" 1091500\n"), Some(1091500));
+        assert_eq!(parse_app_id("0"), None);
+        assert_eq!(parse_app_id("12345678901234567890"), None);
+        assert_eq!(parse_app_id("steam"), None);
+        assert_eq!(parse_app_id(""), None);
+    }
+
+    #[test]
+    fn parent_pid_skips_the_comm_field() {
+        assert_eq!(
+            parse_parent_pid("4242 (game.exe) S 4100 4242 4100 0 -1 4194304"),
+            Some(4100)
+        );
+        assert_eq!(
+            parse_parent_pid("77 (Web Content (x)) R 1 77 77 0 -1"),
+            Some(1)
+        );
+        assert_eq!(parse_parent_pid("77 (truncated"), None);
+        assert_eq!(parse_parent_pid("77 (game) S"), None);
+    }
+
+    #[test]
+    fn environment_prefers_steam_app_id() {
+        assert_eq!(
+            parse_environment(b"HOME=/home/me\0SteamGameId=440\0SteamAppId=570\0"),
+            Some(Some(570))
+        );
+        assert_eq!(
+            parse_environment(b"SteamGameId=12345678901234567890\0"),
+            Some(None)
+        );
+        assert_eq!(parse_environment(b"HOME=/home/me\0PATH=/usr/bin\0"), None);
+    }
+}
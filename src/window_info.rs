@@ -0,0 +1,139 @@
+use crate::steam::{self, SteamGame};
+use crate::wine;
+use crate::x11_watcher::{self, Atoms};
+use log::debug;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+// Upper bound on cached window identities
+const INFO_CACHE_LIMIT: usize = 64;
+
+/// Identity of a focused client window, used by the rule engine and the
+/// Steam and Wine detection paths
+#[derive(Debug, Default, Clone)]
+pub struct WindowInfo {
+    pub instance: Option<String>,
+    pub class: Option<String>,
+    pub title: Option<String>,
+    pub executable: Option<PathBuf>,
+    pub steam: Option<SteamGame>,
+    pub is_wine: bool,
+}
+
+/// Identities of windows seen by detection, keyed by window and process so
+/// `/proc` is read once per window rather than on every pass. Window ids
+/// belong to one X server: clear the cache when reconnecting.
+#[derive(Debug, Default)]
+pub struct WindowInfoCache {
+    entries: HashMap<(x11::xlib::Window, Option<u32>), WindowInfo>,
+}
+
+impl WindowInfoCache {
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+impl WindowInfo {
+    /// Collect WM_CLASS, _NET_WM_NAME, _NET_WM_PID (resolved to an executable
+    /// through /proc) and Steam identification for a client window.
+    /// `focused` is the window that actually holds input focus, which Steam
+    /// games sometimes tag instead of the client toplevel. Only the title is
+    /// re-read for a window already in `cache`.
+    pub fn query(
+        display: *mut x11::xlib::Display,
+        atoms: &Atoms,
+        window: x11::xlib::Window,
+        focused: x11::xlib::Window,
+        cache: &mut WindowInfoCache,
+    ) -> Self {
+        let title = read_string_property(display, window, atoms.net_wm_name)
+            .or_else(|| read_string_property(display, window, x11::xlib::XA_WM_NAME));
+        let pid = read_cardinal(display, window, atoms.net_wm_pid);
+        if let Some(info) = cache.entries.get(&(window, pid)) {
+            return WindowInfo {
+                title,
+                ..info.clone()
+            };
+        }
+
+        let (instance, class) = read_wm_class(display, window);
+        let executable = pid.and_then(|pid| std::fs::read_link(format!("/proc/{}/exe", pid)).ok());
+        let steam = steam::identify(display, atoms.steam_game, &[window, focused], pid);
+        let is_wine = wine::is_wine_window(
+            instance.as_deref(),
+            class.as_deref(),
+            executable.as_deref(),
+            pid,
+        );
+        let info = WindowInfo {
+            instance,
+            class,
+            title,
+            executable,
+            steam,
+            is_wine,
+        };
+        debug!("Window {} info: {:?}", window, info);
+        if cache.entries.len() >= INFO_CACHE_LIMIT {
+            cache.entries.clear();
+        }
+        cache.entries.insert((window, pid), info.clone());
+        info
+    }
+
+    pub fn steam_app_id(&self) -> Option<u32> {
+        self.steam.as_ref().and_then(|game| game.app_id)
+    }
+}
+
+// WM_CLASS as (instance, class)
+fn read_wm_class(
+    display: *mut x11::xlib::Display,
+    window: x11::xlib::Window,
+) -> (Option<String>, Option<String>) {
+    unsafe {
+        let mut hint: x11::xlib::XClassHint = std::mem::zeroed();
+        if x11::xlib::XGetClassHint(display, window, &mut hint) == 0 {
+            return (None, None);
+        }
+        let take = |p: *mut std::os::raw::c_char| {
+            if p.is_null() {
+                return None;
+            }
+            let value = std::ffi::CStr::from_ptr(p).to_string_lossy().into_owned();
+            x11::xlib::XFree(p as *mut _);
+            Some(value)
+        };
+        (take(hint.res_name), take(hint.res_class))
+    }
+}
+
+// First value of a format-32 CARDINAL property
+pub fn read_cardinal(
+    display: *mut x11::xlib::Display,
+    window: x11::xlib::Window,
+    atom: x11::xlib::Atom,
+) -> Option<u32> {
+    if atom == 0 {
+        return None;
+    }
+    x11_watcher::read_property_u32s(display, window, atom)?
+        .first()
+        .map(|v| *v as u32)
+}
+
+// Read a format-8 text property (UTF8_STRING or STRING)
+fn read_string_property(
+    display: *mut x11::xlib::Display,
+    window: x11::xlib::Window,
+    atom: x11::xlib::Atom,
+) -> Option<String> {
+    if atom == 0 {
+        return None;
+    }
+    match x11_watcher::read_property(display, window, atom, 1024)? {
+        (_, 8, bytes) => Some(String::from_utf8_lossy(&bytes).into_owned()),
+        _ => None,
+    }
+}
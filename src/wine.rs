@@ -0,0 +1,122 @@
+use log::debug;
+use std::path::Path;
+
+// Executables a Wine/Proton process shows up as in /proc/<pid>/exe
+const WINE_EXECUTABLES: &[&str] = &[
+    "wine",
+    "wine64",
+    "wine-preloader",
+    "wine64-preloader",
+    "wineserver",
+];
+
+/// Recognise a Wine/Proton window from its WM_CLASS (Wine sets the
+/// instance/class to the Windows executable name, e.g. `game.exe`), the
+/// process executable, or `WINEPREFIX` in the process environment.
+pub fn is_wine_window(
+    instance: Option<&str>,
+    class: Option<&str>,
+    executable: Option<&Path>,
+    pid: Option<u32>,
+) -> bool {
+    let exe_class = [instance, class]
+        .into_iter()
+        .flatten()
+        .any(|name| name.to_ascii_lowercase().ends_with(".exe"));
+    if exe_class {
+        debug!(
+            "Wine window detected from WM_CLASS {:?}/{:?}",
+            instance, class
+        );
+        return true;
+    }
+
+    let wine_exe = executable
+        .and_then(|exe| exe.file_name())
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| WINE_EXECUTABLES.contains(&name));
+    if wine_exe {
+        debug!("Wine window detected from executable {:?}", executable);
+        return true;
+    }
+
+    let Some(pid) = pid else {
+        return false;
+    };
+    let Ok(environ) = std::fs::read(format!("/proc/{}/environ", pid)) else {
+        return false;
+    };
+    let has_prefix = has_wine_prefix(&environ);
+    if has_prefix {
+        debug!(
+            "Wine window detected from WINEPREFIX in environment of pid {}",
+            pid
+        );
+    }
+    has_prefix
+}
+
+// WINEPREFIX set in the NUL-separated contents of /proc/<pid>/environ
+fn has_wine_prefix(environ: &[u8]) -> bool {
+    environ
+        .split(|b| *b == 0)
+        .any(|entry| entry.starts_with(b"WINEPREFIX="))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exe_wm_class_is_wine() {
+        assert!(is_wine_window(
+            Some("game.exe"),
+            Some("game.exe"),
+            None,
+            None
+        ));
+        assert!(is_wine_window(None, Some("Launcher.EXE"), None, None));
+        assert!(!is_wine_window(
+            Some("firefox"),
+            Some("Firefox"),
+            None,
+            None
+        ));
+        assert!(!is_wine_window(Some("exe"), None, None, None));
+    }
+
+    #[test]
+    fn wine_loader_executable_is_wine() {
+        for exe in [
+            "/usr/bin/wine64",
+            "/opt/proton/files/bin/wine64-preloader",
+            "wineserver",
+        ] {
+            assert!(
+                is_wine_window(None, None, Some(Path::new(exe)), None),
+                "{}",
+                exe
+            );
+        }
+        assert!(!is_wine_window(
+            None,
+            None,
+            Some(Path::new("/usr/bin/winecfg-helper")),
+            None
+        ));
+        assert!(!is_wine_window(
+            None,
+            None,
+            Some(Path::new("/usr/bin/steam")),
+            None
+        ));
+    }
+
+    #[test]
+    fn wineprefix_in_environment_is_wine() {
+        assert!(has_wine_prefix(b"HOME=/home/u\0WINEPREFIX=/home/u/.wine\0"));
+        assert!(has_wine_prefix(b"WINEPREFIX=/pfx"));
+        assert!(!has_wine_prefix(b"HOME=/home/u\0MY_WINEPREFIX=/pfx\0"));
+        assert!(!has_wine_prefix(b""));
+    }
+}
@@ -0,0 +1,810 @@
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_int, c_long, c_uchar, c_ulong, c_void};
+use std::os::unix::io::RawFd;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU8, Ordering};
+use std::sync::OnceLock;
+
+// The x11 crate declares XFixesSelectCursorInput with a by-value Display,
+// so bind it ourselves with the correct pointer signature.
+#[link(name = "Xfixes")]
+extern "C" {
+    fn XFixesSelectCursorInput(
+        dpy: *mut x11::xlib::Display,
+        win: x11::xlib::Window,
+        event_mask: c_ulong,
+    );
+}
+
+// XSetIOErrorExitHandler is not bound by the x11 crate and only exists
+// since libX11 1.7, so it is looked up at runtime
+type SetIoErrorExitHandler = unsafe extern "C" fn(
+    *mut x11::xlib::Display,
+    Option<unsafe extern "C" fn(*mut x11::xlib::Display, *mut c_void)>,
+    *mut c_void,
+);
+
+// From <X11/extensions/xfixeswire.h>
+const XFIXES_CURSOR_NOTIFY: c_int = 1;
+const XFIXES_DISPLAY_CURSOR_NOTIFY_MASK: c_ulong = 1;
+
+// A cursor whose pixels all have alpha below this is treated as hidden
+const HIDDEN_ALPHA_THRESHOLD: c_ulong = 16;
+// Upper bound on cached cursor_serial -> hidden results
+const CURSOR_CACHE_LIMIT: usize = 256;
+
+// Connection whose protocol errors are recorded, null when none is. The
+// handler is process-wide and also runs for the warp and XTest connections
+// on other threads, which must not clobber the detection result.
+static TRAPPED_DISPLAY: AtomicPtr<x11::xlib::Display> = AtomicPtr::new(ptr::null_mut());
+// Error code of the last X protocol error on TRAPPED_DISPLAY, 0 if none
+static LAST_ERROR: AtomicU8 = AtomicU8::new(0);
+
+unsafe extern "C" fn handle_error(
+    display: *mut x11::xlib::Display,
+    event: *mut x11::xlib::XErrorEvent,
+) -> c_int {
+    let event = &*event;
+    debug!(
+        "X error {} (request {}.{}) on resource {}",
+        event.error_code, event.request_code, event.minor_code, event.resourceid
+    );
+    if display == TRAPPED_DISPLAY.load(Ordering::Relaxed) {
+        LAST_ERROR.store(event.error_code, Ordering::Relaxed);
+    }
+    0
+}
+
+unsafe extern "C" fn handle_io_error(_display: *mut x11::xlib::Display) -> c_int {
+    warn!("Lost connection to the X server");
+    0
+}
+
+// Runs instead of Xlib's exit(); `data` points at the connection's lost flag
+unsafe extern "C" fn handle_io_error_exit(_display: *mut x11::xlib::Display, data: *mut c_void) {
+    if let Some(lost) = (data as *const AtomicBool).as_ref() {
+        lost.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Install process-wide Xlib error handlers. Protocol errors (such as
+/// BadWindow when the focused window is destroyed mid-query) are logged and
+/// recorded for `take_error` instead of being printed by Xlib, and IO
+/// errors are logged instead of killing the daemon.
+pub fn install_error_handlers() {
+    unsafe {
+        x11::xlib::XSetErrorHandler(Some(handle_error));
+        x11::xlib::XSetIOErrorHandler(Some(handle_io_error));
+    }
+}
+
+fn io_error_exit_handler() -> Option<SetIoErrorExitHandler> {
+    static SYMBOL: OnceLock<Option<SetIoErrorExitHandler>> = OnceLock::new();
+    *SYMBOL.get_or_init(|| {
+        let symbol = unsafe { libc::dlsym(libc::RTLD_DEFAULT, c"XSetIOErrorExitHandler".as_ptr()) };
+        if symbol.is_null() {
+            warn!("libX11 older than 1.7, losing the X connection ends the daemon");
+            return None;
+        }
+        Some(unsafe { std::mem::transmute::<*mut c_void, SetIoErrorExitHandler>(symbol) })
+    })
+}
+
+/// Keep the process alive when the connection to `display` is lost; `lost`
+/// is set instead. Afterwards the only valid call on `display` is
+/// `XCloseDisplay`, and `lost` must outlive the display. Without libX11 1.7
+/// only the `install_error_handlers` handler runs and Xlib exits.
+pub unsafe fn survive_io_errors(display: *mut x11::xlib::Display, lost: &AtomicBool) {
+    if let Some(set_handler) = io_error_exit_handler() {
+        set_handler(
+            display,
+            Some(handle_io_error_exit),
+            lost as *const AtomicBool as *mut c_void,
+        );
+    }
+}
+
+/// Start recording X protocol errors on `display`, forgetting any
+/// previously recorded one. Errors on other connections are only logged.
+pub fn clear_error(display: *mut x11::xlib::Display) {
+    LAST_ERROR.store(0, Ordering::Relaxed);
+    TRAPPED_DISPLAY.store(display, Ordering::Relaxed);
+}
+
+/// Stop recording and return the error code of the X protocol error seen
+/// on the trapped display since `clear_error`, if any
+pub fn take_error() -> Option<u8> {
+    TRAPPED_DISPLAY.store(ptr::null_mut(), Ordering::Relaxed);
+    Some(LAST_ERROR.swap(0, Ordering::Relaxed)).filter(|code| *code != 0)
+}
+
+// Hidden if 1x1 or smaller, or if every ARGB pixel is (nearly) transparent.
+// Engines often hide the cursor with a fully transparent 16x16/32x32 image.
+fn analyse_cursor_image(cursor: &x11::xfixes::XFixesCursorImage) -> bool {
+    if cursor.width <= 1 && cursor.height <= 1 {
+        return true;
+    }
+    if cursor.pixels.is_null() {
+        return false;
+    }
+    let len = cursor.width as usize * cursor.height as usize;
+    // Xlib stores each 32-bit ARGB pixel in a C long
+    let pixels = unsafe { std::slice::from_raw_parts(cursor.pixels, len) };
+    pixels
+        .iter()
+        .all(|p| (p >> 24) & 0xff < HIDDEN_ALPHA_THRESHOLD)
+}
+
+// Check whether the current cursor image is hidden, returned with its
+// cursor_serial. Results are cached by XFixes cursor_serial so each cursor
+// is analysed once.
+fn cursor_image_hidden(
+    display: *mut x11::xlib::Display,
+    cache: &mut HashMap<c_ulong, bool>,
+) -> (bool, c_ulong) {
+    unsafe {
+        let cursor_image = x11::xfixes::XFixesGetCursorImage(display);
+        if cursor_image.is_null() {
+            return (true, 0); // assume hidden if we can't get image
+        }
+        let cursor = &*cursor_image;
+        let hidden = if let Some(&hidden) = cache.get(&cursor.cursor_serial) {
+            hidden
+        } else {
+            let hidden = analyse_cursor_image(cursor);
+            if cache.len() >= CURSOR_CACHE_LIMIT {
+                cache.clear();
+            }
+            cache.insert(cursor.cursor_serial, hidden);
+            debug!(
+                "Analysed cursor serial={} {}x{} hidden={}",
+                cursor.cursor_serial, cursor.width, cursor.height, hidden
+            );
+            hidden
+        };
+        let serial = cursor.cursor_serial;
+        x11::xlib::XFree(cursor_image as *mut _);
+        (hidden, serial)
+    }
+}
+
+/// A cursor image from XFixes, for showing the game's cursor on Wayland
+#[derive(Debug, Clone)]
+pub struct CursorImage {
+    pub width: i32,
+    pub height: i32,
+    pub hotspot: (i32, i32),
+    pub serial: c_ulong,
+    /// Premultiplied ARGB, row by row
+    pub pixels: Vec<u32>,
+}
+
+/// Atoms used by detection, interned once when the connection is opened
+#[derive(Debug, Clone, Copy)]
+pub struct Atoms {
+    pub wm_state: x11::xlib::Atom,
+    pub net_supported: x11::xlib::Atom,
+    pub net_active_window: x11::xlib::Atom,
+    pub net_wm_state: x11::xlib::Atom,
+    pub net_wm_state_fullscreen: x11::xlib::Atom,
+    pub net_wm_name: x11::xlib::Atom,
+    pub net_wm_pid: x11::xlib::Atom,
+    pub steam_game: x11::xlib::Atom,
+}
+
+impl Atoms {
+    // Intern every atom in a single round trip
+    fn intern(display: *mut x11::xlib::Display) -> Self {
+        const NAMES: [&std::ffi::CStr; 8] = [
+            c"WM_STATE",
+            c"_NET_SUPPORTED",
+            c"_NET_ACTIVE_WINDOW",
+            c"_NET_WM_STATE",
+            c"_NET_WM_STATE_FULLSCREEN",
+            c"_NET_WM_NAME",
+            c"_NET_WM_PID",
+            c"STEAM_GAME",
+        ];
+        let mut names = NAMES.map(|name| name.as_ptr() as *mut std::os::raw::c_char);
+        let mut atoms: [x11::xlib::Atom; 8] = [0; 8];
+        unsafe {
+            x11::xlib::XInternAtoms(
+                display,
+                names.as_mut_ptr(),
+                names.len() as c_int,
+                0,
+                atoms.as_mut_ptr(),
+            );
+        }
+        let [wm_state, net_supported, net_active_window, net_wm_state, net_wm_state_fullscreen, net_wm_name, net_wm_pid, steam_game] =
+            atoms;
+        Atoms {
+            wm_state,
+            net_supported,
+            net_active_window,
+            net_wm_state,
+            net_wm_state_fullscreen,
+            net_wm_name,
+            net_wm_pid,
+            steam_game,
+        }
+    }
+}
+
+/// Read up to `long_length` 32-bit units of a property as (type, format,
+/// raw item bytes). Format-32 items come back as C longs, as Xlib hands them
+/// out. Returns None when the property is missing.
+pub fn read_property(
+    display: *mut x11::xlib::Display,
+    window: x11::xlib::Window,
+    property: x11::xlib::Atom,
+    long_length: c_long,
+) -> Option<(x11::xlib::Atom, c_int, Vec<u8>)> {
+    unsafe {
+        let mut actual_type: x11::xlib::Atom = 0;
+        let mut actual_format: c_int = 0;
+        let mut nitems: c_ulong = 0;
+        let mut bytes_after: c_ulong = 0;
+        let mut prop: *mut c_uchar = ptr::null_mut();
+        let res = x11::xlib::XGetWindowProperty(
+            display,
+            window,
+            property,
+            0,
+            long_length,
+            0,
+            x11::xlib::AnyPropertyType as x11::xlib::Atom,
+            &mut actual_type,
+            &mut actual_format,
+            &mut nitems,
+            &mut bytes_after,
+            &mut prop,
+        );
+        if prop.is_null() {
+            return None;
+        }
+        let item_size = match actual_format {
+            8 => 1,
+            16 => std::mem::size_of::<std::os::raw::c_short>(),
+            32 => std::mem::size_of::<c_ulong>(),
+            _ => 0,
+        };
+        let bytes = std::slice::from_raw_parts(prop, nitems as usize * item_size).to_vec();
+        x11::xlib::XFree(prop as *mut _);
+        if res != 0 {
+            return None;
+        }
+        Some((actual_type, actual_format, bytes))
+    }
+}
+
+// Read a format-32 property (ATOM, WINDOW, CARDINAL...) as a list of values.
+// Returns None when the property is missing or has a different format.
+pub fn read_property_u32s(
+    display: *mut x11::xlib::Display,
+    window: x11::xlib::Window,
+    property: x11::xlib::Atom,
+) -> Option<Vec<c_ulong>> {
+    match read_property(display, window, property, 1024)? {
+        (_, 32, bytes) => Some(
+            bytes
+                .chunks_exact(std::mem::size_of::<c_ulong>())
+                .map(|item| c_ulong::from_ne_bytes(item.try_into().unwrap()))
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// Whether `property` is set to a non-empty value of any type and format
+pub fn has_property(
+    display: *mut x11::xlib::Display,
+    window: x11::xlib::Window,
+    property: x11::xlib::Atom,
+) -> bool {
+    read_property(display, window, property, 1).is_some_and(|(_, _, bytes)| !bytes.is_empty())
+}
+
+// Query a window's parent and children; None if the window is gone
+fn query_tree(
+    display: *mut x11::xlib::Display,
+    window: x11::xlib::Window,
+) -> Option<(x11::xlib::Window, Vec<x11::xlib::Window>)> {
+    unsafe {
+        let mut root: x11::xlib::Window = 0;
+        let mut parent: x11::xlib::Window = 0;
+        let mut children: *mut x11::xlib::Window = ptr::null_mut();
+        let mut nchildren: std::os::raw::c_uint = 0;
+        if x11::xlib::XQueryTree(
+            display,
+            window,
+            &mut root,
+            &mut parent,
+            &mut children,
+            &mut nchildren,
+        ) == 0
+        {
+            return None;
+        }
+        let list = if children.is_null() {
+            Vec::new()
+        } else {
+            let list = std::slice::from_raw_parts(children, nchildren as usize).to_vec();
+            x11::xlib::XFree(children as *mut _);
+            list
+        };
+        Some((parent, list))
+    }
+}
+
+// Depth-first search below `window` for the first descendant carrying WM_STATE
+fn find_client_below(
+    display: *mut x11::xlib::Display,
+    window: x11::xlib::Window,
+    wm_state: x11::xlib::Atom,
+) -> Option<x11::xlib::Window> {
+    let (_, children) = query_tree(display, window)?;
+    // Children are returned bottom-to-top; the client is usually the topmost
+    for &child in children.iter().rev() {
+        if read_property_u32s(display, child, wm_state).is_some() {
+            return Some(child);
+        }
+    }
+    children
+        .iter()
+        .rev()
+        .find_map(|&child| find_client_below(display, child, wm_state))
+}
+
+/// Resolve the client toplevel (the window carrying `WM_STATE`) for a
+/// focused window. Input focus often lands on a child of the client or on
+/// the WM frame around it, so walk up the tree first and then down from
+/// the frame. Returns `window` unchanged when no client is found.
+pub fn resolve_client_window(
+    display: *mut x11::xlib::Display,
+    atoms: &Atoms,
+    window: x11::xlib::Window,
+) -> x11::xlib::Window {
+    let wm_state = atoms.wm_state;
+    if wm_state == 0 {
+        return window;
+    }
+    let root = unsafe { x11::xlib::XDefaultRootWindow(display) };
+
+    // Walk up: the focused window itself or one of its ancestors is the client
+    let mut current = window;
+    let mut frame = window;
+    while current != 0 && current != root {
+        if read_property_u32s(display, current, wm_state).is_some() {
+            if current != window {
+                debug!("Resolved focused window {} to client {}", window, current);
+            }
+            return current;
+        }
+        frame = current;
+        current = match query_tree(display, current) {
+            Some((parent, _)) => parent,
+            None => return window,
+        };
+    }
+
+    // Walk down from the root-level ancestor (the WM frame) to its client
+    match find_client_below(display, frame, wm_state) {
+        Some(client) => {
+            debug!(
+                "Resolved focused window {} to client {} below frame {}",
+                window, client, frame
+            );
+            client
+        }
+        None => window,
+    }
+}
+
+// List XRandR monitors as root-relative rectangles (x, y, width, height).
+// Callers must have checked that RandR 1.5 monitors are available.
+fn xrandr_monitors(display: *mut x11::xlib::Display) -> Vec<(i32, i32, i32, i32)> {
+    unsafe {
+        let root = x11::xlib::XDefaultRootWindow(display);
+        let mut count: c_int = 0;
+        let monitors = x11::xrandr::XRRGetMonitors(display, root, 1, &mut count);
+        if monitors.is_null() {
+            return Vec::new();
+        }
+        let rects = std::slice::from_raw_parts(monitors, count.max(0) as usize)
+            .iter()
+            .map(|m| (m.x, m.y, m.width, m.height))
+            .collect();
+        x11::xrandr::XRRFreeMonitors(monitors);
+        rects
+    }
+}
+
+/// The daemon's long-lived X11 connection. It is shared by detection and
+/// subscribed to XFixes cursor notifications, root window property
+/// changes (`_NET_ACTIVE_WINDOW`) and geometry and `_NET_WM_STATE` changes
+/// of the active client. Atoms and extension queries are done once when
+/// the connection is opened.
+///
+/// The connection fd is meant to be watched by the glib main loop; call
+/// `process_events` whenever it becomes readable.
+pub struct X11Watcher {
+    display: *mut x11::xlib::Display,
+    // Passed on to the other connections the daemon opens to the same server
+    display_name: CString,
+    root: x11::xlib::Window,
+    atoms: Atoms,
+    // XFixes event base when cursor notifications are available
+    xfixes_event_base: Option<c_int>,
+    // RandR 1.5 monitor queries are available
+    has_xrandr_monitors: bool,
+    cursor_hidden: bool,
+    // XFixes cursor_serial of the current cursor, 0 if unknown
+    cursor_serial: c_ulong,
+    // cursor_serial -> hidden, filled as cursors are first seen
+    cursor_cache: HashMap<c_ulong, bool>,
+    // None when the window manager does not publish _NET_ACTIVE_WINDOW
+    active_window: Option<x11::xlib::Window>,
+    // Client toplevel of the active window whose structure and property
+    // changes are selected, 0 if none
+    active_client: x11::xlib::Window,
+    // Set by the IO error exit handler; boxed so its address stays stable
+    lost: Box<AtomicBool>,
+}
+
+impl X11Watcher {
+    pub fn open(display_name: &CStr) -> Option<Self> {
+        unsafe {
+            let display = x11::xlib::XOpenDisplay(display_name.as_ptr());
+            if display.is_null() {
+                debug!("Could not open X display");
+                return None;
+            }
+            let lost = Box::new(AtomicBool::new(false));
+            survive_io_errors(display, &lost);
+            let root = x11::xlib::XDefaultRootWindow(display);
+
+            let xfixes_event_base = Self::query_xfixes(display);
+            let has_xrandr_monitors = Self::query_xrandr_monitors(display);
+            if !has_xrandr_monitors {
+                info!("RandR 1.5 monitors not available, using the root size for fullscreen");
+            }
+
+            x11::xlib::XSelectInput(display, root, x11::xlib::PropertyChangeMask);
+            let mut cursor_cache = HashMap::new();
+            let (cursor_hidden, cursor_serial) = match xfixes_event_base {
+                Some(_) => {
+                    XFixesSelectCursorInput(display, root, XFIXES_DISPLAY_CURSOR_NOTIFY_MASK);
+                    let (hidden, serial) = cursor_image_hidden(display, &mut cursor_cache);
+                    info!(
+                        "Watching XFixes cursor notifications (cursor_hidden={})",
+                        hidden
+                    );
+                    (hidden, serial)
+                }
+                None => (false, 0),
+            };
+            x11::xlib::XFlush(display);
+
+            let mut watcher = X11Watcher {
+                display,
+                display_name: display_name.to_owned(),
+                root,
+                atoms: Atoms::intern(display),
+                xfixes_event_base,
+                has_xrandr_monitors,
+                cursor_hidden,
+                cursor_serial,
+                cursor_cache,
+                active_window: None,
+                active_client: 0,
+                lost,
+            };
+            watcher.refresh_active_window();
+            watcher.watch_active_client();
+            match watcher.active_window {
+                Some(w) => info!("Tracking focus via _NET_ACTIVE_WINDOW (active={})", w),
+                None => info!("_NET_ACTIVE_WINDOW not published, falling back to XGetInputFocus"),
+            }
+            Some(watcher)
+        }
+    }
+
+    // XFixes event base, if cursor notifications (XFixes 2.0+) are supported
+    unsafe fn query_xfixes(display: *mut x11::xlib::Display) -> Option<c_int> {
+        let mut event_base: c_int = 0;
+        let mut error_base: c_int = 0;
+        if x11::xfixes::XFixesQueryExtension(display, &mut event_base, &mut error_base) == 0 {
+            warn!("XFixes extension not available, cursor detection disabled");
+            return None;
+        }
+        let mut major: c_int = 2;
+        let mut minor: c_int = 0;
+        // minor is an in/out argument despite the *const in the x11 binding
+        x11::xfixes::XFixesQueryVersion(display, &mut major, ptr::addr_of_mut!(minor));
+        if major < 2 {
+            warn!("XFixes {} is too old for cursor notifications", major);
+            return None;
+        }
+        Some(event_base)
+    }
+
+    // Whether RandR 1.5 (XRRGetMonitors) is supported
+    unsafe fn query_xrandr_monitors(display: *mut x11::xlib::Display) -> bool {
+        let mut event_base: c_int = 0;
+        let mut error_base: c_int = 0;
+        if x11::xrandr::XRRQueryExtension(display, &mut event_base, &mut error_base) == 0 {
+            return false;
+        }
+        let mut major: c_int = 0;
+        let mut minor: c_int = 0;
+        if x11::xrandr::XRRQueryVersion(display, &mut major, &mut minor) == 0 {
+            return false;
+        }
+        (major, minor) >= (1, 5)
+    }
+
+    // Re-read _NET_ACTIVE_WINDOW; returns true if the tracked value changed
+    fn refresh_active_window(&mut self) -> bool {
+        let net_active_window = self.atoms.net_active_window;
+        let supported = read_property_u32s(self.display, self.root, self.atoms.net_supported)
+            .is_some_and(|atoms| atoms.contains(&net_active_window));
+        let active = if supported {
+            read_property_u32s(self.display, self.root, net_active_window)
+                .map(|v| v.first().copied().unwrap_or(0))
+        } else {
+            None
+        };
+        let changed = active != self.active_window;
+        self.active_window = active;
+        changed
+    }
+
+    // Follow resizes, moves and fullscreen state changes of the active
+    // client, so geometry-only transitions don't need polling
+    fn watch_active_client(&mut self) {
+        let client = match self.active_window {
+            Some(window) if window != 0 && window != self.root => {
+                resolve_client_window(self.display, &self.atoms, window)
+            }
+            _ => 0,
+        };
+        if client == self.active_client {
+            return;
+        }
+        unsafe {
+            if self.active_client != 0 {
+                x11::xlib::XSelectInput(self.display, self.active_client, x11::xlib::NoEventMask);
+            }
+            if client != 0 {
+                x11::xlib::XSelectInput(
+                    self.display,
+                    client,
+                    x11::xlib::StructureNotifyMask | x11::xlib::PropertyChangeMask,
+                );
+            }
+            x11::xlib::XFlush(self.display);
+        }
+        debug!("Watching active client {}", client);
+        self.active_client = client;
+    }
+
+    /// True once the connection to the X server has been lost; the watcher
+    /// should then be dropped and a new one opened
+    pub fn is_connection_lost(&self) -> bool {
+        self.lost.load(Ordering::Relaxed)
+    }
+
+    pub fn fd(&self) -> RawFd {
+        unsafe { x11::xlib::XConnectionNumber(self.display) }
+    }
+
+    /// Connection shared by detection; valid as long as the watcher lives
+    pub fn display(&self) -> *mut x11::xlib::Display {
+        self.display
+    }
+
+    /// Name the connection was opened with, for opening others to the
+    /// same server
+    pub fn display_name(&self) -> &CStr {
+        &self.display_name
+    }
+
+    pub fn root(&self) -> x11::xlib::Window {
+        self.root
+    }
+
+    pub fn atoms(&self) -> &Atoms {
+        &self.atoms
+    }
+
+    /// Cursor visibility from XFixes notifications; false when XFixes is
+    /// unavailable
+    pub fn cursor_hidden(&self) -> bool {
+        self.cursor_hidden
+    }
+
+    /// XFixes cursor_serial of the current cursor, changing whenever the
+    /// cursor does; 0 when XFixes is unavailable
+    pub fn cursor_serial(&self) -> c_ulong {
+        self.cursor_serial
+    }
+
+    /// Current cursor image; None when XFixes is unavailable
+    pub fn cursor_image(&self) -> Option<CursorImage> {
+        self.xfixes_event_base?;
+        unsafe {
+            let cursor_image = x11::xfixes::XFixesGetCursorImage(self.display);
+            if cursor_image.is_null() {
+                return None;
+            }
+            let cursor = &*cursor_image;
+            let len = cursor.width as usize * cursor.height as usize;
+            // Xlib stores each 32-bit ARGB pixel in a C long
+            let pixels = if cursor.pixels.is_null() {
+                Vec::new()
+            } else {
+                std::slice::from_raw_parts(cursor.pixels, len)
+                    .iter()
+                    .map(|p| *p as u32)
+                    .collect()
+            };
+            let image = CursorImage {
+                width: i32::from(cursor.width),
+                height: i32::from(cursor.height),
+                hotspot: (i32::from(cursor.xhot), i32::from(cursor.yhot)),
+                serial: cursor.cursor_serial,
+                pixels,
+            };
+            x11::xlib::XFree(cursor_image as *mut _);
+            Some(image)
+        }
+    }
+
+    /// XRandR monitors as root-relative rectangles (x, y, width, height);
+    /// empty when RandR 1.5 is unavailable
+    pub fn monitors(&self) -> Vec<(i32, i32, i32, i32)> {
+        if !self.has_xrandr_monitors {
+            return Vec::new();
+        }
+        xrandr_monitors(self.display)
+    }
+
+    /// Active window as published by the window manager. `Some(0)` means no
+    /// window is active; `None` means the WM does not support
+    /// `_NET_ACTIVE_WINDOW` and callers should query `XGetInputFocus`.
+    pub fn active_window(&self) -> Option<x11::xlib::Window> {
+        self.active_window
+    }
+
+    /// Window holding focus: `_NET_ACTIVE_WINDOW` when the WM publishes it,
+    /// else `XGetInputFocus`. None when nothing but the root is focused.
+    /// This may be a child of the client or its WM frame; see
+    /// `resolve_client_window`.
+    pub fn focused_window(&self) -> Option<x11::xlib::Window> {
+        let focus = match self.active_window {
+            Some(active) => active,
+            None => unsafe {
+                let mut focus: x11::xlib::Window = 0;
+                let mut revert_to: c_int = 0;
+                x11::xlib::XGetInputFocus(self.display, &mut focus, &mut revert_to);
+                focus
+            },
+        };
+        (focus != 0 && focus != self.root).then_some(focus)
+    }
+
+    /// Drain every queued X event. Returns true when watched state changed
+    /// and the lock decision needs to be re-evaluated.
+    pub fn process_events(&mut self) -> bool {
+        let mut changed = false;
+        unsafe {
+            while !self.is_connection_lost() && x11::xlib::XPending(self.display) > 0 {
+                let mut event: x11::xlib::XEvent = std::mem::zeroed();
+                x11::xlib::XNextEvent(self.display, &mut event);
+
+                let cursor_notify = self
+                    .xfixes_event_base
+                    .map(|base| base + XFIXES_CURSOR_NOTIFY);
+                if Some(event.get_type()) == cursor_notify {
+                    let notify = &*(&event as *const x11::xlib::XEvent
+                        as *const x11::xfixes::XFixesCursorNotifyEvent);
+                    self.cursor_serial = notify.cursor_serial;
+                    let hidden = match self.cursor_cache.get(&notify.cursor_serial) {
+                        Some(&hidden) => hidden,
+                        None => cursor_image_hidden(self.display, &mut self.cursor_cache).0,
+                    };
+                    debug!(
+                        "XFixes cursor notify: serial={} hidden={}",
+                        notify.cursor_serial, hidden
+                    );
+                    if hidden != self.cursor_hidden {
+                        self.cursor_hidden = hidden;
+                        changed = true;
+                    }
+                } else if event.get_type() == x11::xlib::PropertyNotify {
+                    let property = event.property;
+                    if property.window == self.root
+                        && (property.atom == self.atoms.net_active_window
+                            || property.atom == self.atoms.net_supported)
+                        && self.refresh_active_window()
+                    {
+                        debug!("Active window changed: {:?}", self.active_window);
+                        self.watch_active_client();
+                        changed = true;
+                    } else if property.window == self.active_client
+                        && property.atom == self.atoms.net_wm_state
+                    {
+                        changed = true;
+                    }
+                } else if event.get_type() == x11::xlib::ConfigureNotify {
+                    changed |= event.configure.window == self.active_client;
+                } else if event.get_type() == x11::xlib::DestroyNotify
+                    && event.destroy_window.window == self.active_client
+                {
+                    // Gone already, nothing left to deselect
+                    self.active_client = 0;
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+}
+
+impl Drop for X11Watcher {
+    fn drop(&mut self) {
+        // Also valid after an IO error; Xlib skips the final sync then
+        unsafe {
+            x11::xlib::XCloseDisplay(self.display);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cursor(width: u16, height: u16, pixels: &mut [c_ulong]) -> x11::xfixes::XFixesCursorImage {
+        x11::xfixes::XFixesCursorImage {
+            x: 0,
+            y: 0,
+            width,
+            height,
+            xhot: 0,
+            yhot: 0,
+            cursor_serial: 1,
+            pixels: pixels.as_mut_ptr(),
+            atom: 0,
+            name: ptr::null(),
+        }
+    }
+
+    #[test]
+    fn tiny_cursors_are_hidden() {
+        let mut pixels = [0xffff_ffff];
+        assert!(analyse_cursor_image(&cursor(1, 1, &mut pixels)));
+        assert!(analyse_cursor_image(&cursor(0, 0, &mut [])));
+    }
+
+    #[test]
+    fn transparent_cursors_are_hidden() {
+        let mut pixels = [0x0000_0000; 16];
+        assert!(analyse_cursor_image(&cursor(4, 4, &mut pixels)));
+        // Colour without alpha, and alpha just under the threshold
+        let mut pixels = [0x00ff_ffff; 16];
+        pixels[5] = 0x0f00_0000;
+        assert!(analyse_cursor_image(&cursor(4, 4, &mut pixels)));
+    }
+
+    #[test]
+    fn one_opaque_pixel_makes_a_cursor_visible() {
+        let mut pixels = [0x0000_0000; 16];
+        pixels[15] = 0x1000_0000;
+        assert!(!analyse_cursor_image(&cursor(4, 4, &mut pixels)));
+        let mut pixels = [0xff00_0000; 16];
+        assert!(!analyse_cursor_image(&cursor(4, 4, &mut pixels)));
+    }
+}
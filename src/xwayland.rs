@@ -0,0 +1,326 @@
+use log::{debug, info};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int};
+use std::os::unix::fs::MetadataExt;
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+const X11_SOCKET_DIR: &str = "/tmp/.X11-unix";
+// Discovery is redone after this long even if the sockets look the same,
+// e.g. when Xwayland was restarted on another of the user's displays
+const DISCOVERY_TTL: Duration = Duration::from_secs(30);
+// Longest the main loop waits for the systemd user manager's environment
+const SYSTEMCTL_TIMEOUT: Duration = Duration::from_millis(500);
+const MIT_MAGIC_COOKIE: &[u8] = b"MIT-MAGIC-COOKIE-1";
+
+// Display and Xauthority cookie found for a running XWayland
+#[derive(Debug, Clone)]
+struct Found {
+    display: String,
+    // (authorization name, data) from the Xauthority file
+    cookie: Option<(Vec<u8>, Vec<u8>)>,
+    source: &'static str,
+}
+
+// Last discovery, with the sockets it was based on and when it ran
+struct Discovery {
+    sockets: Vec<u32>,
+    found: Found,
+    at: Instant,
+}
+
+/// Finds the XWayland display to connect to. A `DISPLAY` inherited at
+/// startup is always used as is; otherwise XWayland is discovered from its
+/// sockets and its process, since on-demand XWayland often starts after the
+/// daemon. Only a running Xwayland counts: Mutter listens on the X socket
+/// before it spawns Xwayland, and connecting there would start it.
+pub struct XWaylandLocator {
+    inherited: Option<CString>,
+    // Reused while the user's X sockets stay the same
+    discovery: Option<Discovery>,
+}
+
+impl XWaylandLocator {
+    pub fn new() -> Self {
+        let inherited = std::env::var("DISPLAY")
+            .ok()
+            .filter(|d| !d.is_empty())
+            .and_then(|d| CString::new(d).ok());
+        if inherited.is_none() {
+            info!("DISPLAY not set, will look for XWayland when it starts");
+        }
+        XWaylandLocator {
+            inherited,
+            discovery: None,
+        }
+    }
+
+    /// Display name to pass to `XOpenDisplay`, if an X server is available.
+    /// The process environment is left alone since other threads may read
+    /// it; the cookie of a discovered display is installed with
+    /// `XSetAuthorization` for every connection opened afterwards.
+    pub fn locate(&mut self) -> Option<CString> {
+        if let Some(display) = &self.inherited {
+            return Some(display.clone());
+        }
+        let uid = std::fs::metadata("/proc/self").ok()?.uid();
+        // Cheap check first: no socket of ours means no XWayland to talk to
+        let sockets = owned_sockets(uid);
+        if sockets.is_empty() {
+            self.discovery = None;
+            return None;
+        }
+        let fresh = self
+            .discovery
+            .as_ref()
+            .is_some_and(|d| d.sockets == sockets && d.at.elapsed() < DISCOVERY_TTL);
+        if !fresh {
+            let found = discover(uid, &sockets)?;
+            debug!(
+                "Found XWayland display {} via {}",
+                found.display, found.source
+            );
+            self.discovery = Some(Discovery {
+                sockets,
+                found,
+                at: Instant::now(),
+            });
+        }
+        let found = &self.discovery.as_ref()?.found;
+        set_authorization(found.cookie.as_ref());
+        CString::new(found.display.clone()).ok()
+    }
+
+    /// Forget a discovered display after XWayland went away, so the next
+    /// `locate` looks for it again
+    pub fn detach(&mut self) {
+        if self.discovery.take().is_some() {
+            debug!("Forgetting discovered XWayland display");
+        }
+    }
+}
+
+// Use `cookie` for X connections opened from now on; None restores Xlib's
+// default of reading $XAUTHORITY or ~/.Xauthority. Xlib guards this with
+// its global lock once XInitThreads has run, as main does at startup.
+fn set_authorization(cookie: Option<&(Vec<u8>, Vec<u8>)>) {
+    let (mut name, mut data) = cookie.cloned().unwrap_or_default();
+    let as_ptr = |bytes: &mut Vec<u8>| {
+        if bytes.is_empty() {
+            std::ptr::null_mut()
+        } else {
+            bytes.as_mut_ptr() as *mut c_char
+        }
+    };
+    // Xlib copies both buffers
+    unsafe {
+        x11::xlib::XSetAuthorization(
+            as_ptr(&mut name),
+            name.len() as c_int,
+            as_ptr(&mut data),
+            data.len() as c_int,
+        );
+    }
+}
+
+// `sockets` are the display numbers of the user's X sockets. None until an
+// Xwayland process serves one of them; the cookie comes from its -auth
+// file, else from the XAUTHORITY the session exported to systemd.
+fn discover(uid: u32, sockets: &[u32]) -> Option<Found> {
+    let has_socket = |display: &str| display_number(display).is_some_and(|n| sockets.contains(&n));
+
+    let mut found = from_xwayland_process(uid).filter(|f| has_socket(&f.display))?;
+    if found.cookie.is_none() {
+        found.cookie = from_systemd_environment()
+            .filter(|env| env.display == found.display)
+            .and_then(|env| env.cookie);
+    }
+    Some(found)
+}
+
+// Display numbers of the X sockets owned by `uid`, lowest first
+fn owned_sockets(uid: u32) -> Vec<u32> {
+    let Ok(entries) = std::fs::read_dir(X11_SOCKET_DIR) else {
+        return Vec::new();
+    };
+    let mut displays: Vec<u32> = entries
+        .flatten()
+        .filter(|entry| entry.metadata().is_ok_and(|m| m.uid() == uid))
+        .filter_map(|entry| entry.file_name().to_str()?.strip_prefix('X')?.parse().ok())
+        .collect();
+    displays.sort_unstable();
+    displays
+}
+
+// ":1" or ":1.0" -> 1
+fn display_number(display: &str) -> Option<u32> {
+    let number = display.strip_prefix(':')?;
+    number.split('.').next()?.parse().ok()
+}
+
+// An Xwayland process of ours, e.g. `Xwayland :0 -rootless ... -auth <file>`
+fn from_xwayland_process(uid: u32) -> Option<Found> {
+    for entry in std::fs::read_dir("/proc").ok()?.flatten() {
+        let path = entry.path();
+        if !entry.metadata().is_ok_and(|m| m.uid() == uid) {
+            continue;
+        }
+        let Ok(comm) = std::fs::read_to_string(path.join("comm")) else {
+            continue;
+        };
+        if comm.trim() != "Xwayland" {
+            continue;
+        }
+        let Ok(cmdline) = std::fs::read(path.join("cmdline")) else {
+            continue;
+        };
+        let args: Vec<String> = cmdline
+            .split(|b| *b == 0)
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect();
+        let Some(display) = args.iter().skip(1).find(|arg| arg.starts_with(':')) else {
+            continue;
+        };
+        let cookie = args
+            .iter()
+            .position(|arg| arg == "-auth")
+            .and_then(|i| args.get(i + 1))
+            .and_then(|file| read_cookie(file, display));
+        debug!("Xwayland process {:?} serves {}", path, display);
+        return Some(Found {
+            display: display.clone(),
+            cookie,
+            source: "the Xwayland process",
+        });
+    }
+    None
+}
+
+// DISPLAY/XAUTHORITY exported to the systemd user manager by the session.
+// Runs on the main loop, so systemctl is given SYSTEMCTL_TIMEOUT to answer.
+fn from_systemd_environment() -> Option<Found> {
+    let mut child = Command::new("systemctl")
+        .args(["--user", "show-environment"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    let mut pipe = child.stdout.take()?;
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut stdout = Vec::new();
+        let _ = pipe.read_to_end(&mut stdout);
+        let _ = sender.send(stdout);
+    });
+    let Ok(stdout) = receiver.recv_timeout(SYSTEMCTL_TIMEOUT) else {
+        debug!("systemctl --user show-environment did not answer in time");
+        // Killing it closes the pipe, which ends the reader thread
+        let _ = child.kill();
+        let _ = child.wait();
+        return None;
+    };
+    if !child.wait().ok()?.success() {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&stdout);
+    let env: HashMap<&str, &str> = stdout
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .collect();
+    let display = env.get("DISPLAY").filter(|d| !d.is_empty())?;
+    Some(Found {
+        display: display.to_string(),
+        cookie: env
+            .get("XAUTHORITY")
+            .and_then(|file| read_cookie(file, display)),
+        source: "the systemd user environment",
+    })
+}
+
+// MIT-MAGIC-COOKIE-1 for `display` from the Xauthority file at `path`
+fn read_cookie(path: &str, display: &str) -> Option<(Vec<u8>, Vec<u8>)> {
+    let contents = std::fs::read(path)
+        .map_err(|e| debug!("Could not read Xauthority file {}: {}", path, e))
+        .ok()?;
+    let cookie = parse_xauthority(&contents, display_number(display)?);
+    if cookie.is_none() {
+        debug!("No cookie for {} in {}", display, path);
+    }
+    cookie
+}
+
+// Xauthority entries are family (u16) followed by address, display number,
+// authorization name and data, each a big-endian u16 length and its bytes.
+// Prefers an entry for `number`; entries without a number match any display.
+fn parse_xauthority(mut contents: &[u8], number: u32) -> Option<(Vec<u8>, Vec<u8>)> {
+    fn field<'a>(contents: &mut &'a [u8]) -> Option<&'a [u8]> {
+        let len = u16::from_be_bytes([*contents.first()?, *contents.get(1)?]) as usize;
+        let value = contents.get(2..2 + len)?;
+        *contents = &contents[2 + len..];
+        Some(value)
+    }
+    let number = number.to_string();
+    let mut wildcard = None;
+    while contents.len() >= 2 {
+        contents = &contents[2..]; // family
+        let _address = field(&mut contents)?;
+        let entry_number = field(&mut contents)?;
+        let name = field(&mut contents)?;
+        let data = field(&mut contents)?;
+        if name != MIT_MAGIC_COOKIE {
+            continue;
+        }
+        if entry_number == number.as_bytes() {
+            return Some((name.to_vec(), data.to_vec()));
+        }
+        if entry_number.is_empty() && wildcard.is_none() {
+            wildcard = Some((name.to_vec(), data.to_vec()));
+        }
+    }
+    wildcard
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(number: &str, name: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0x01, 0x00]; // FamilyLocal
+        for field in [&b"host"[..], number.as_bytes(), name, data] {
+            bytes.extend_from_slice(&(field.len() as u16).to_be_bytes());
+            bytes.extend_from_slice(field);
+        }
+        bytes
+    }
+
+    #[test]
+    fn display_numbers() {
+        assert_eq!(display_number(":0"), Some(0));
+        assert_eq!(display_number(":12.0"), Some(12));
+        assert_eq!(display_number("host:0"), None);
+        assert_eq!(display_number(":"), None);
+    }
+
+    #[test]
+    fn cookie_for_the_display_number_wins() {
+        let mut contents = entry("", MIT_MAGIC_COOKIE, b"any");
+        contents.extend(entry("0", b"XDM-AUTHORIZATION-1", b"xdm"));
+        contents.extend(entry("1", MIT_MAGIC_COOKIE, b"one"));
+        contents.extend(entry("0", MIT_MAGIC_COOKIE, b"zero"));
+        let cookie = |number| parse_xauthority(&contents, number).map(|(_, data)| data);
+        assert_eq!(cookie(0).as_deref(), Some(&b"zero"[..]));
+        assert_eq!(cookie(1).as_deref(), Some(&b"one"[..]));
+        assert_eq!(cookie(2).as_deref(), Some(&b"any"[..]));
+    }
+
+    #[test]
+    fn truncated_xauthority_files_are_rejected() {
+        let contents = entry("0", MIT_MAGIC_COOKIE, b"zero");
+        assert!(parse_xauthority(&contents[..contents.len() - 1], 0).is_none());
+        assert!(parse_xauthority(&[], 0).is_none());
+    }
+}